@@ -0,0 +1,430 @@
+use fake_device::{ManufacturerData, ServiceData};
+use fake_discovery_session::normalize_uuid;
+use std::error::Error;
+use std::fmt;
+
+const AD_FLAGS: u8 = 0x01;
+const AD_INCOMPLETE_UUIDS_16: u8 = 0x02;
+const AD_COMPLETE_UUIDS_16: u8 = 0x03;
+const AD_INCOMPLETE_UUIDS_32: u8 = 0x04;
+const AD_COMPLETE_UUIDS_32: u8 = 0x05;
+const AD_INCOMPLETE_UUIDS_128: u8 = 0x06;
+const AD_COMPLETE_UUIDS_128: u8 = 0x07;
+const AD_SHORTENED_LOCAL_NAME: u8 = 0x08;
+const AD_COMPLETE_LOCAL_NAME: u8 = 0x09;
+const AD_TX_POWER: u8 = 0x0A;
+const AD_SLAVE_CONNECTION_INTERVAL_RANGE: u8 = 0x12;
+const AD_SERVICE_DATA_16: u8 = 0x16;
+const AD_APPEARANCE: u8 = 0x19;
+const AD_SERVICE_DATA_32: u8 = 0x20;
+const AD_SERVICE_DATA_128: u8 = 0x21;
+const AD_URI: u8 = 0x24;
+const AD_MANUFACTURER_DATA: u8 = 0xFF;
+
+const BASE_UUID_SUFFIX: &str = "-0000-1000-8000-00805f9b34fb";
+
+// The schemes of the Bluetooth URI scheme name string mapping that are
+// worth shortening. Any other URI is sent whole after the empty scheme, 0x01.
+const URI_SCHEMES: &[(u8, &str)] = &[(0x16, "http:"), (0x17, "https:")];
+const URI_EMPTY_SCHEME: u8 = 0x01;
+
+pub const FLAG_LE_LIMITED_DISCOVERABLE: u8 = 0x01;
+pub const FLAG_LE_GENERAL_DISCOVERABLE: u8 = 0x02;
+pub const FLAG_BR_EDR_NOT_SUPPORTED: u8 = 0x04;
+pub const FLAG_SIMULTANEOUS_LE_BR_EDR_CONTROLLER: u8 = 0x08;
+pub const FLAG_SIMULTANEOUS_LE_BR_EDR_HOST: u8 = 0x10;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AdvertisingDataError {
+    /// The AD structure starting at the given offset runs past the end of the data.
+    Truncated(usize),
+    /// The AD structure of the given type has a length its type does not allow.
+    InvalidLength(u8, usize),
+    /// The name or URI in the AD structure of the given type is not UTF-8.
+    InvalidUtf8(u8),
+    /// The uuid is neither a 16, 32 nor a 128 bit uuid.
+    InvalidUuid(String),
+    /// The URI uses a scheme code this model does not know.
+    UnknownUriScheme(u8),
+    /// An AD structure of the given type would need more than 254 bytes of data.
+    TooLong(u8, usize),
+}
+
+impl fmt::Display for AdvertisingDataError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AdvertisingDataError::Truncated(offset) =>
+                write!(f, "The AD structure at offset {} runs past the end of the data.", offset),
+            AdvertisingDataError::InvalidLength(ad_type, length) =>
+                write!(f, "The AD structure of type 0x{:02X} has an invalid length: {}.", ad_type, length),
+            AdvertisingDataError::InvalidUtf8(ad_type) =>
+                write!(f, "The AD structure of type 0x{:02X} is not valid UTF-8.", ad_type),
+            AdvertisingDataError::InvalidUuid(ref uuid) => write!(f, "Invalid uuid: {}.", uuid),
+            AdvertisingDataError::UnknownUriScheme(scheme) => write!(f, "Unknown URI scheme: 0x{:02X}.", scheme),
+            AdvertisingDataError::TooLong(ad_type, length) =>
+                write!(f, "The AD structure of type 0x{:02X} is too long: {} bytes.", ad_type, length),
+        }
+    }
+}
+
+impl Error for AdvertisingDataError {}
+
+/// The contents of an advertisement or scan response, as a list of AD
+/// structures (Core Specification Supplement, Part A).
+///
+/// Service uuids and service data uuids are kept in their 128 bit form and
+/// encoded in the shortest form they fit. AD types the model does not know
+/// are kept in `other`, so decoding and encoding again keeps them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AdvertisingData {
+    pub flags: Option<u8>,
+    pub complete_local_name: Option<String>,
+    pub shortened_local_name: Option<String>,
+    pub service_uuids: Vec<String>,
+    /// Encodes the uuid lists with the "incomplete list" AD types.
+    pub incomplete_service_uuids: bool,
+    pub service_data: ServiceData,
+    pub manufacturer_data: ManufacturerData,
+    pub tx_power: Option<i8>,
+    pub appearance: Option<u16>,
+    /// The minimum and maximum connection interval, in units of 1.25 ms.
+    pub slave_connection_interval_range: Option<(u16, u16)>,
+    pub uri: Option<String>,
+    pub other: Vec<(u8, Vec<u8>)>,
+}
+
+impl AdvertisingData {
+    pub fn new() -> AdvertisingData {
+        AdvertisingData::default()
+    }
+
+    /// The complete local name, or the shortened one if that is all there is.
+    pub fn get_local_name(&self) -> Option<String> {
+        self.complete_local_name.clone().or_else(|| self.shortened_local_name.clone())
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, AdvertisingDataError> {
+        let mut structures: Vec<(u8, Vec<u8>)> = vec![];
+
+        if let Some(flags) = self.flags {
+            structures.push((AD_FLAGS, vec![flags]));
+        }
+
+        let mut uuids_16 = vec![];
+        let mut uuids_32 = vec![];
+        let mut uuids_128 = vec![];
+        for uuid in &self.service_uuids {
+            match shorten_uuid(uuid)? {
+                ShortUuid::Uuid16(uuid) => uuids_16.extend_from_slice(&uuid.to_le_bytes()),
+                ShortUuid::Uuid32(uuid) => uuids_32.extend_from_slice(&uuid.to_le_bytes()),
+                ShortUuid::Uuid128(uuid) => uuids_128.extend_from_slice(&uuid),
+            }
+        }
+        let (type_16, type_32, type_128) = if self.incomplete_service_uuids {
+            (AD_INCOMPLETE_UUIDS_16, AD_INCOMPLETE_UUIDS_32, AD_INCOMPLETE_UUIDS_128)
+        } else {
+            (AD_COMPLETE_UUIDS_16, AD_COMPLETE_UUIDS_32, AD_COMPLETE_UUIDS_128)
+        };
+        for (ad_type, uuids) in [(type_16, uuids_16), (type_32, uuids_32), (type_128, uuids_128)] {
+            if !uuids.is_empty() {
+                structures.push((ad_type, uuids));
+            }
+        }
+
+        if let Some(ref name) = self.shortened_local_name {
+            structures.push((AD_SHORTENED_LOCAL_NAME, name.clone().into_bytes()));
+        }
+        if let Some(ref name) = self.complete_local_name {
+            structures.push((AD_COMPLETE_LOCAL_NAME, name.clone().into_bytes()));
+        }
+        if let Some(tx_power) = self.tx_power {
+            structures.push((AD_TX_POWER, vec![tx_power as u8]));
+        }
+        if let Some((min, max)) = self.slave_connection_interval_range {
+            let mut data = min.to_le_bytes().to_vec();
+            data.extend_from_slice(&max.to_le_bytes());
+            structures.push((AD_SLAVE_CONNECTION_INTERVAL_RANGE, data));
+        }
+
+        // Hash map order is random; sort to keep the encoding stable.
+        let mut service_data: Vec<(&String, &Vec<u8>)> = self.service_data.iter().collect();
+        service_data.sort();
+        for (uuid, value) in service_data {
+            let (ad_type, mut data) = match shorten_uuid(uuid)? {
+                ShortUuid::Uuid16(uuid) => (AD_SERVICE_DATA_16, uuid.to_le_bytes().to_vec()),
+                ShortUuid::Uuid32(uuid) => (AD_SERVICE_DATA_32, uuid.to_le_bytes().to_vec()),
+                ShortUuid::Uuid128(uuid) => (AD_SERVICE_DATA_128, uuid.to_vec()),
+            };
+            data.extend_from_slice(value);
+            structures.push((ad_type, data));
+        }
+
+        if let Some(appearance) = self.appearance {
+            structures.push((AD_APPEARANCE, appearance.to_le_bytes().to_vec()));
+        }
+        if let Some(ref uri) = self.uri {
+            structures.push((AD_URI, encode_uri(uri)));
+        }
+
+        let mut manufacturer_data: Vec<(&u16, &Vec<u8>)> = self.manufacturer_data.iter().collect();
+        manufacturer_data.sort();
+        for (company_id, value) in manufacturer_data {
+            let mut data = company_id.to_le_bytes().to_vec();
+            data.extend_from_slice(value);
+            structures.push((AD_MANUFACTURER_DATA, data));
+        }
+
+        structures.extend(self.other.iter().cloned());
+
+        let mut encoded = vec![];
+        for (ad_type, data) in structures {
+            if data.len() > 254 {
+                return Err(AdvertisingDataError::TooLong(ad_type, data.len()));
+            }
+            encoded.push(data.len() as u8 + 1);
+            encoded.push(ad_type);
+            encoded.extend(data);
+        }
+        Ok(encoded)
+    }
+
+    /// Decodes a list of AD structures. A zero length byte ends the data, as
+    /// it does for the zero padding of a fixed size advertising payload.
+    pub fn decode(bytes: &[u8]) -> Result<AdvertisingData, AdvertisingDataError> {
+        let mut ad = AdvertisingData::new();
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let length = bytes[offset] as usize;
+            if length == 0 {
+                break;
+            }
+            if offset + 1 + length > bytes.len() {
+                return Err(AdvertisingDataError::Truncated(offset));
+            }
+            let ad_type = bytes[offset + 1];
+            let data = &bytes[offset + 2..offset + 1 + length];
+            ad.decode_structure(ad_type, data)?;
+            offset += 1 + length;
+        }
+        Ok(ad)
+    }
+
+    fn decode_structure(&mut self, ad_type: u8, data: &[u8]) -> Result<(), AdvertisingDataError> {
+        let invalid_length = AdvertisingDataError::InvalidLength(ad_type, data.len());
+        match ad_type {
+            AD_FLAGS => {
+                if data.len() != 1 {
+                    return Err(invalid_length);
+                }
+                self.flags = Some(data[0]);
+            },
+            AD_INCOMPLETE_UUIDS_16 | AD_COMPLETE_UUIDS_16 |
+            AD_INCOMPLETE_UUIDS_32 | AD_COMPLETE_UUIDS_32 |
+            AD_INCOMPLETE_UUIDS_128 | AD_COMPLETE_UUIDS_128 => {
+                let size = match ad_type {
+                    AD_INCOMPLETE_UUIDS_16 | AD_COMPLETE_UUIDS_16 => 2,
+                    AD_INCOMPLETE_UUIDS_32 | AD_COMPLETE_UUIDS_32 => 4,
+                    _ => 16,
+                };
+                let uuids = data.chunks_exact(size);
+                if !uuids.remainder().is_empty() {
+                    return Err(invalid_length);
+                }
+                self.service_uuids.extend(uuids.map(expand_uuid));
+                if matches!(ad_type, AD_INCOMPLETE_UUIDS_16 | AD_INCOMPLETE_UUIDS_32 | AD_INCOMPLETE_UUIDS_128) {
+                    self.incomplete_service_uuids = true;
+                }
+            },
+            AD_SHORTENED_LOCAL_NAME => self.shortened_local_name = Some(decode_string(ad_type, data)?),
+            AD_COMPLETE_LOCAL_NAME => self.complete_local_name = Some(decode_string(ad_type, data)?),
+            AD_TX_POWER => {
+                if data.len() != 1 {
+                    return Err(invalid_length);
+                }
+                self.tx_power = Some(data[0] as i8);
+            },
+            AD_SLAVE_CONNECTION_INTERVAL_RANGE => {
+                if data.len() != 4 {
+                    return Err(invalid_length);
+                }
+                self.slave_connection_interval_range = Some((u16::from_le_bytes([data[0], data[1]]),
+                                                             u16::from_le_bytes([data[2], data[3]])));
+            },
+            AD_SERVICE_DATA_16 | AD_SERVICE_DATA_32 | AD_SERVICE_DATA_128 => {
+                let size = match ad_type {
+                    AD_SERVICE_DATA_16 => 2,
+                    AD_SERVICE_DATA_32 => 4,
+                    _ => 16,
+                };
+                if data.len() < size {
+                    return Err(invalid_length);
+                }
+                self.service_data.insert(expand_uuid(&data[..size]), data[size..].to_vec());
+            },
+            AD_APPEARANCE => {
+                if data.len() != 2 {
+                    return Err(invalid_length);
+                }
+                self.appearance = Some(u16::from_le_bytes([data[0], data[1]]));
+            },
+            AD_URI => {
+                if data.is_empty() {
+                    return Err(invalid_length);
+                }
+                let rest = decode_string(ad_type, &data[1..])?;
+                let scheme = match data[0] {
+                    URI_EMPTY_SCHEME => "",
+                    code => match URI_SCHEMES.iter().find(|&&(c, _)| c == code) {
+                        Some(&(_, scheme)) => scheme,
+                        None => return Err(AdvertisingDataError::UnknownUriScheme(code)),
+                    },
+                };
+                self.uri = Some(format!("{}{}", scheme, rest));
+            },
+            AD_MANUFACTURER_DATA => {
+                if data.len() < 2 {
+                    return Err(invalid_length);
+                }
+                self.manufacturer_data.insert(u16::from_le_bytes([data[0], data[1]]), data[2..].to_vec());
+            },
+            _ => self.other.push((ad_type, data.to_vec())),
+        }
+        Ok(())
+    }
+}
+
+/// One advertisement as received by the adapter, see
+/// `FakeBluetoothAdapter::simulate_advertisement`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AdvertisementPacket {
+    pub rssi: Option<i16>,
+    pub tx_power: Option<i16>,
+    pub name: Option<String>,
+    pub uuids: Vec<String>,
+    pub manufacturer_data: ManufacturerData,
+    pub service_data: ServiceData,
+    pub appearance: Option<u16>,
+}
+
+impl AdvertisementPacket {
+    pub fn new() -> AdvertisementPacket {
+        AdvertisementPacket::default()
+    }
+}
+
+impl<'a> From<&'a AdvertisingData> for AdvertisementPacket {
+    fn from(ad: &'a AdvertisingData) -> AdvertisementPacket {
+        AdvertisementPacket {
+            rssi: None,
+            tx_power: ad.tx_power.map(i16::from),
+            name: ad.get_local_name(),
+            uuids: ad.service_uuids.clone(),
+            manufacturer_data: ad.manufacturer_data.clone(),
+            service_data: ad.service_data.clone(),
+            appearance: ad.appearance,
+        }
+    }
+}
+
+// Fails for anything that is not a 16, 32 or 128 bit uuid.
+#[cfg(feature = "scenario")]
+pub(crate) fn check_uuid(uuid: &str) -> Result<(), AdvertisingDataError> {
+    shorten_uuid(uuid).map(|_| ())
+}
+
+enum ShortUuid {
+    Uuid16(u16),
+    Uuid32(u32),
+    // Little endian, as sent over the air.
+    Uuid128([u8; 16]),
+}
+
+fn shorten_uuid(uuid: &str) -> Result<ShortUuid, AdvertisingDataError> {
+    let invalid_uuid = || AdvertisingDataError::InvalidUuid(String::from(uuid));
+    let normalized = normalize_uuid(uuid);
+    // The 8-4-4-4-12 layout, dashes included.
+    let is_well_formed = normalized.len() == 36 &&
+                         normalized.bytes().enumerate().all(|(i, b)| match i {
+                             8 | 13 | 18 | 23 => b == b'-',
+                             _ => b.is_ascii_hexdigit(),
+                         });
+    if !is_well_formed {
+        return Err(invalid_uuid());
+    }
+    let hex: String = normalized.chars().filter(|&c| c != '-').collect();
+    if normalized.ends_with(BASE_UUID_SUFFIX) {
+        let short = u32::from_str_radix(&normalized[..8], 16).map_err(|_| invalid_uuid())?;
+        if short <= u32::from(u16::MAX) {
+            return Ok(ShortUuid::Uuid16(short as u16));
+        }
+        return Ok(ShortUuid::Uuid32(short));
+    }
+    let mut bytes = [0u8; 16];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).map_err(|_| invalid_uuid())?;
+    }
+    bytes.reverse();
+    Ok(ShortUuid::Uuid128(bytes))
+}
+
+// Turns a little endian 16, 32 or 128 bit uuid into its 128 bit string form.
+fn expand_uuid(bytes: &[u8]) -> String {
+    let hex: String = bytes.iter().rev().map(|b| format!("{:02x}", b)).collect();
+    if hex.len() != 32 {
+        return normalize_uuid(&hex);
+    }
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+fn decode_string(ad_type: u8, data: &[u8]) -> Result<String, AdvertisingDataError> {
+    String::from_utf8(data.to_vec()).map_err(|_| AdvertisingDataError::InvalidUtf8(ad_type))
+}
+
+fn encode_uri(uri: &str) -> Vec<u8> {
+    for &(code, scheme) in URI_SCHEMES {
+        if let Some(rest) = uri.strip_prefix(scheme) {
+            let mut data = vec![code];
+            data.extend_from_slice(rest.as_bytes());
+            return data;
+        }
+    }
+    let mut data = vec![URI_EMPTY_SCHEME];
+    data.extend_from_slice(uri.as_bytes());
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{shorten_uuid, AdvertisingData, AdvertisingDataError, ShortUuid};
+
+    #[test]
+    fn uuids_are_shortened() {
+        assert!(matches!(shorten_uuid("180F"), Ok(ShortUuid::Uuid16(0x180f))));
+        assert!(matches!(shorten_uuid("0000180f-0000-1000-8000-00805f9b34fb"), Ok(ShortUuid::Uuid16(0x180f))));
+        assert!(matches!(shorten_uuid("12345678-0000-1000-8000-00805f9b34fb"), Ok(ShortUuid::Uuid32(0x12345678))));
+        match shorten_uuid("12345678-1234-5678-9abc-def012345678") {
+            Ok(ShortUuid::Uuid128(bytes)) => assert_eq!(bytes[0], 0x78),
+            _ => panic!("not a 128 bit uuid"),
+        }
+    }
+
+    #[test]
+    fn malformed_uuids_are_rejected() {
+        for &uuid in &[
+            "1234567-81234-5678-9abc-def012345678",
+            "12345678123456789abcdef012345678",
+            "12345678-1234-5678-9abc-def01234567-",
+            "12345678-1234-5678-9abc-def01234567g",
+            "12345678:1234-5678-9abc-def012345678",
+            "180",
+        ] {
+            assert_eq!(shorten_uuid(uuid).err(), Some(AdvertisingDataError::InvalidUuid(String::from(uuid))), "{}", uuid);
+        }
+
+        let data = AdvertisingData {
+            service_uuids: vec![String::from("1234567-81234-5678-9abc-def012345678")],
+            ..AdvertisingData::default()
+        };
+        assert_eq!(data.encode(), Err(AdvertisingDataError::InvalidUuid(String::from("1234567-81234-5678-9abc-def012345678"))));
+    }
+}
@@ -0,0 +1,213 @@
+use error::BlurmockError;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+type TimerCallback = Box<dyn FnOnce() + Send>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TimerId(u64);
+
+#[derive(Clone, Copy, Debug)]
+enum ClockMode {
+    Real(Instant),
+    Manual,
+}
+
+struct ClockState {
+    now: Duration,
+    next_timer_id: u64,
+    // Keyed by deadline first, so manual timers fire in order. Timers with the
+    // same deadline fire in the order they were scheduled.
+    timers: BTreeMap<(Duration, TimerId), TimerCallback>,
+    sleeping_threads: usize,
+}
+
+struct ClockInner {
+    mode: ClockMode,
+    state: Mutex<ClockState>,
+    advanced: Condvar,
+}
+
+/// The time source of an adapter tree. A real clock follows the wall clock and
+/// fires timers from background threads. A manual clock only moves on
+/// `advance`, which fires the due timers on the calling thread, so tests that
+/// depend on timing run instantly and deterministically.
+///
+/// Clones share the same time and timers.
+#[derive(Clone)]
+pub struct FakeClock {
+    inner: Arc<ClockInner>,
+}
+
+impl FakeClock {
+    pub fn real() -> FakeClock {
+        FakeClock::with_mode(ClockMode::Real(Instant::now()))
+    }
+
+    pub fn manual() -> FakeClock {
+        FakeClock::with_mode(ClockMode::Manual)
+    }
+
+    fn with_mode(mode: ClockMode) -> FakeClock {
+        FakeClock {
+            inner: Arc::new(ClockInner {
+                mode,
+                state: Mutex::new(ClockState {
+                    now: Duration::from_secs(0),
+                    next_timer_id: 0,
+                    timers: BTreeMap::new(),
+                    sleeping_threads: 0,
+                }),
+                advanced: Condvar::new(),
+            }),
+        }
+    }
+
+    pub fn is_manual(&self) -> bool {
+        match self.inner.mode {
+            ClockMode::Manual => true,
+            ClockMode::Real(_) => false,
+        }
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, ClockState>, BlurmockError> {
+        match self.inner.state.lock() {
+            Ok(guard) => Ok(guard),
+            Err(_) => Err(BlurmockError::LockPoisoned),
+        }
+    }
+
+    /// The time elapsed since the clock was created.
+    pub fn now(&self) -> Result<Duration, BlurmockError> {
+        match self.inner.mode {
+            ClockMode::Real(started_at) => Ok(started_at.elapsed()),
+            ClockMode::Manual => Ok(self.lock()?.now),
+        }
+    }
+
+    /// Runs `callback` once `delay` has passed. Callbacks must not block on the
+    /// clock: on a manual clock they run inside `advance`.
+    pub fn schedule<F>(&self, delay: Duration, callback: F) -> Result<TimerId, BlurmockError>
+        where F: FnOnce() + Send + 'static
+    {
+        let deadline = self.now()? + delay;
+        let mut state = self.lock()?;
+        let id = TimerId(state.next_timer_id);
+        state.next_timer_id += 1;
+        state.timers.insert((deadline, id), Box::new(callback));
+        drop(state);
+
+        if let ClockMode::Real(_) = self.inner.mode {
+            let clock = self.clone();
+            thread::spawn(move || {
+                thread::sleep(delay);
+                if let Some(callback) = clock.take_timer(deadline, id) {
+                    callback();
+                }
+            });
+        }
+        Ok(id)
+    }
+
+    fn take_timer(&self, deadline: Duration, id: TimerId) -> Option<TimerCallback> {
+        match self.lock() {
+            Ok(mut state) => state.timers.remove(&(deadline, id)),
+            Err(_) => None,
+        }
+    }
+
+    /// Cancels a timer. Returns false if it already fired or was cancelled.
+    pub fn cancel(&self, id: TimerId) -> Result<bool, BlurmockError> {
+        let mut state = self.lock()?;
+        let key = state.timers.keys().find(|&&(_, timer_id)| timer_id == id).cloned();
+        Ok(match key {
+            Some(key) => state.timers.remove(&key).is_some(),
+            None => false,
+        })
+    }
+
+    pub fn pending_timer_count(&self) -> Result<usize, BlurmockError> {
+        Ok(self.lock()?.timers.len())
+    }
+
+    /// The number of threads blocked in `sleep` on a manual clock. A test can
+    /// wait for it before calling `advance`, so the sleeping thread is sure to
+    /// be woken.
+    pub fn sleeping_thread_count(&self) -> Result<usize, BlurmockError> {
+        Ok(self.lock()?.sleeping_threads)
+    }
+
+    /// Moves a manual clock forward, firing every timer that falls due, in
+    /// deadline order. Timers scheduled by those callbacks fire too if they
+    /// fall due before the new time. Fails with `NotSupported` on a real clock.
+    pub fn advance(&self, duration: Duration) -> Result<(), BlurmockError> {
+        if !self.is_manual() {
+            return Err(BlurmockError::NotSupported);
+        }
+        let target = self.lock()?.now + duration;
+        loop {
+            let mut state = self.lock()?;
+            let next_key = match state.timers.keys().next() {
+                Some(&key) if key.0 <= target => key,
+                _ => {
+                    state.now = target;
+                    break;
+                },
+            };
+            let callback = state.timers.remove(&next_key);
+            if next_key.0 > state.now {
+                state.now = next_key.0;
+            }
+            drop(state);
+            self.inner.advanced.notify_all();
+            if let Some(callback) = callback {
+                callback();
+            }
+        }
+        self.inner.advanced.notify_all();
+        Ok(())
+    }
+
+    /// Blocks the calling thread for `duration` of clock time. On a manual
+    /// clock this waits until another thread advances the clock far enough,
+    /// so it never returns when the test has no other thread to do that.
+    pub fn sleep(&self, duration: Duration) -> Result<(), BlurmockError> {
+        if duration == Duration::from_secs(0) {
+            return Ok(());
+        }
+        if !self.is_manual() {
+            thread::sleep(duration);
+            return Ok(());
+        }
+        let mut state = self.lock()?;
+        let deadline = state.now + duration;
+        state.sleeping_threads += 1;
+        while state.now < deadline {
+            state = match self.inner.advanced.wait(state) {
+                Ok(guard) => guard,
+                Err(_) => return Err(BlurmockError::LockPoisoned),
+            };
+        }
+        state.sleeping_threads -= 1;
+        Ok(())
+    }
+}
+
+impl Default for FakeClock {
+    fn default() -> FakeClock {
+        FakeClock::real()
+    }
+}
+
+impl fmt::Debug for FakeClock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FakeClock")
+         .field("mode", &self.inner.mode)
+         .field("now", &self.now().ok())
+         .field("pending_timers", &self.pending_timer_count().ok())
+         .finish()
+    }
+}
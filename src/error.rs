@@ -0,0 +1,97 @@
+use advertising_data::AdvertisingDataError;
+use modalias::ModaliasParseError;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ObjectKind {
+    Adapter,
+    Device,
+    Service,
+    Characteristic,
+    Descriptor,
+    AdData,
+}
+
+impl fmt::Display for ObjectKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            ObjectKind::Adapter => "adapter",
+            ObjectKind::Device => "device",
+            ObjectKind::Service => "service",
+            ObjectKind::Characteristic => "characteristic",
+            ObjectKind::Descriptor => "descriptor",
+            ObjectKind::AdData => "ad_data",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BlurmockError {
+    /// A mutex guarding the fake state was poisoned by a panicking thread.
+    LockPoisoned,
+    /// An optional property was read while it had no value.
+    NoValue,
+    /// No object of the given kind exists with the given id.
+    /// `id` is `None` when the first object of a list was requested.
+    NotFound { kind: ObjectKind, id: Option<String> },
+    NotConnected,
+    ConnectionFailed,
+    NotPermitted,
+    NotAuthorized,
+    NotSupported,
+    NotAvailable,
+    InvalidArguments,
+    InProgress,
+    AdapterNotPowered,
+    AdapterNotPresent,
+    StartDiscoveryFailed,
+    StopDiscoveryFailed,
+    NotDiscovering,
+    InvalidModalias(ModaliasParseError),
+    InvalidFlag(String),
+    InvalidAdvertisingData(AdvertisingDataError),
+}
+
+impl fmt::Display for BlurmockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BlurmockError::LockPoisoned => f.write_str("Could not get the value."),
+            BlurmockError::NoValue => f.write_str("The value is not set."),
+            BlurmockError::NotFound { ref kind, id: Some(ref id) } =>
+                write!(f, "No {} exists with the given id: {}.", kind, id),
+            BlurmockError::NotFound { ref kind, id: None } => write!(f, "No {} found.", kind),
+            BlurmockError::NotConnected => f.write_str("Device not connected."),
+            BlurmockError::ConnectionFailed => f.write_str("Could not connect to the device."),
+            BlurmockError::NotPermitted => f.write_str("Operation is not permitted."),
+            BlurmockError::NotAuthorized => f.write_str("Operation is not authorized."),
+            BlurmockError::NotSupported => f.write_str("Operation is not supported."),
+            BlurmockError::NotAvailable => f.write_str("Operation currently not available."),
+            BlurmockError::InvalidArguments => f.write_str("Invalid arguments."),
+            BlurmockError::InProgress => f.write_str("Operation already in progress."),
+            BlurmockError::AdapterNotPowered => f.write_str("Adapter is not powered."),
+            BlurmockError::AdapterNotPresent => f.write_str("Adapter is not present."),
+            BlurmockError::StartDiscoveryFailed => f.write_str("Failed to start discovery session."),
+            BlurmockError::StopDiscoveryFailed => f.write_str("Failed to stop discovery session."),
+            BlurmockError::NotDiscovering => f.write_str("No discovery started."),
+            BlurmockError::InvalidModalias(ref err) => write!(f, "Invalid modalias: {}", err),
+            BlurmockError::InvalidFlag(ref flag) => write!(f, "Unknown flag: {}.", flag),
+            BlurmockError::InvalidAdvertisingData(ref err) => write!(f, "Invalid advertising data: {}", err),
+        }
+    }
+}
+
+impl Error for BlurmockError {}
+
+impl From<ModaliasParseError> for BlurmockError {
+    fn from(err: ModaliasParseError) -> BlurmockError {
+        BlurmockError::InvalidModalias(err)
+    }
+}
+
+impl From<AdvertisingDataError> for BlurmockError {
+    fn from(err: AdvertisingDataError) -> BlurmockError {
+        BlurmockError::InvalidAdvertisingData(err)
+    }
+}
@@ -0,0 +1,127 @@
+use advertising_data::{AdvertisementPacket, AdvertisingData};
+use error::ObjectKind;
+use fake_device::{ManufacturerData, ServiceData};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PropertyValue {
+    None,
+    Bool(bool),
+    U16(u16),
+    U32(u32),
+    I16(i16),
+    String(String),
+    Strings(Vec<String>),
+    Bytes(Vec<u8>),
+    ManufacturerData(ManufacturerData),
+    ServiceData(ServiceData),
+    AdvertisingData(Box<AdvertisingData>),
+    AdvertisingDataList(Vec<AdvertisingData>),
+}
+
+impl From<bool> for PropertyValue {
+    fn from(value: bool) -> PropertyValue {
+        PropertyValue::Bool(value)
+    }
+}
+
+impl From<u16> for PropertyValue {
+    fn from(value: u16) -> PropertyValue {
+        PropertyValue::U16(value)
+    }
+}
+
+impl From<u32> for PropertyValue {
+    fn from(value: u32) -> PropertyValue {
+        PropertyValue::U32(value)
+    }
+}
+
+impl From<i16> for PropertyValue {
+    fn from(value: i16) -> PropertyValue {
+        PropertyValue::I16(value)
+    }
+}
+
+impl From<String> for PropertyValue {
+    fn from(value: String) -> PropertyValue {
+        PropertyValue::String(value)
+    }
+}
+
+impl From<Vec<String>> for PropertyValue {
+    fn from(value: Vec<String>) -> PropertyValue {
+        PropertyValue::Strings(value)
+    }
+}
+
+impl From<Vec<u8>> for PropertyValue {
+    fn from(value: Vec<u8>) -> PropertyValue {
+        PropertyValue::Bytes(value)
+    }
+}
+
+impl From<ManufacturerData> for PropertyValue {
+    fn from(value: ManufacturerData) -> PropertyValue {
+        PropertyValue::ManufacturerData(value)
+    }
+}
+
+impl From<ServiceData> for PropertyValue {
+    fn from(value: ServiceData) -> PropertyValue {
+        PropertyValue::ServiceData(value)
+    }
+}
+
+impl From<AdvertisingData> for PropertyValue {
+    fn from(value: AdvertisingData) -> PropertyValue {
+        PropertyValue::AdvertisingData(Box::new(value))
+    }
+}
+
+impl From<Vec<AdvertisingData>> for PropertyValue {
+    fn from(value: Vec<AdvertisingData>) -> PropertyValue {
+        PropertyValue::AdvertisingDataList(value)
+    }
+}
+
+impl<T: Into<PropertyValue>> From<Option<T>> for PropertyValue {
+    fn from(value: Option<T>) -> PropertyValue {
+        match value {
+            Some(value) => value.into(),
+            None => PropertyValue::None,
+        }
+    }
+}
+
+/// Events broadcast to every receiver returned by `FakeBluetoothAdapter::subscribe`.
+/// Changes anywhere in the adapter's tree are reported on the adapter; adapter
+/// hot-plug events go to `FakeBluetoothManager::subscribe` instead.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FakeBluetoothEvent {
+    /// Sent to the receivers of `FakeBluetoothManager::subscribe`.
+    AdapterAdded { adapter_id: String },
+    /// Sent to the receivers of `FakeBluetoothManager::subscribe`.
+    AdapterRemoved { adapter_id: String },
+    DeviceAdded { adapter_id: String, device_id: String },
+    DeviceRemoved { adapter_id: String, device_id: String },
+    ServiceAdded { device_id: String, service_id: String },
+    ServiceRemoved { device_id: String, service_id: String },
+    CharacteristicAdded { service_id: String, characteristic_id: String },
+    CharacteristicRemoved { service_id: String, characteristic_id: String },
+    DescriptorAdded { characteristic_id: String, descriptor_id: String },
+    DescriptorRemoved { characteristic_id: String, descriptor_id: String },
+    /// Sent for every advertisement passed to
+    /// `FakeBluetoothAdapter::simulate_advertisement`, after the device
+    /// properties were updated from it.
+    AdvertisementReceived { adapter_id: String, device_id: String, packet: Box<AdvertisementPacket> },
+    /// Sent after a property setter changed a value. `property` is the name of
+    /// the field, e.g. `is_connected` or `rssi`. No event is sent when the new
+    /// value equals the old one.
+    PropertyChanged {
+        kind: ObjectKind,
+        object_id: String,
+        property: &'static str,
+        old: PropertyValue,
+        new: PropertyValue,
+    },
+}
@@ -1,9 +1,31 @@
+use advertising_data::{AdvertisementPacket, AdvertisingData};
+use clock::{FakeClock, TimerId};
 use core::ops::Deref;
-use fake_device::FakeBluetoothDevice;
-use fake_discovery_session::FakeBluetoothDiscoverySession;
-use hex;
-use std::error::Error;
+use error::{BlurmockError, ObjectKind};
+use event::{FakeBluetoothEvent, PropertyValue};
+use fake_device::{FakeBluetoothDevice, FakeBluetoothDeviceBuilder};
+use fault::{FaultId, FaultInjector, FaultRule};
+use fake_discovery_session::{DiscoveryFilter, DiscoverySchedule, FakeBluetoothDiscoverySession};
+use latency::{LatencyInjector, LatencyRule};
+use modalias::{Modalias, ModaliasSource};
+use operation::Operation;
+use snapshot::{restore_value, AdapterSnapshot};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+
+// An armed discoverable or pairable timeout. The generation changes on every
+// restart, so a real-clock timer that fires while being replaced does nothing.
+#[derive(Debug, Default)]
+struct AdapterTimeout {
+    generation: u64,
+    timer: Option<TimerId>,
+}
+
+/// A device waiting to be found by discovery, see `add_undiscovered_device`.
+pub type UndiscoveredDevice = (Arc<FakeBluetoothDevice>, DiscoverySchedule);
 
 #[derive(Clone, Debug)]
 pub struct FakeBluetoothAdapter {
@@ -13,7 +35,7 @@ pub struct FakeBluetoothAdapter {
     can_start_discovery: Arc<Mutex<bool>>,
     can_stop_discovery: Arc<Mutex<bool>>,
     devices: Arc<Mutex<Vec<Arc<FakeBluetoothDevice>>>>,
-    ad_datas: Arc<Mutex<Vec<String>>>,
+    ad_datas: Arc<Mutex<Vec<AdvertisingData>>>,
     address: Arc<Mutex<String>>,
     name: Arc<Mutex<String>>,
     alias: Arc<Mutex<String>>,
@@ -25,16 +47,35 @@ pub struct FakeBluetoothAdapter {
     is_discovering: Arc<Mutex<bool>>,
     uuids: Arc<Mutex<Vec<String>>>,
     modalias: Arc<Mutex<String>>,
+    subscribers: Arc<Mutex<Vec<Sender<FakeBluetoothEvent>>>>,
+    discovery_sessions: Arc<Mutex<HashSet<usize>>>,
+    next_discovery_session_id: Arc<Mutex<usize>>,
+    discovery_filters: Arc<Mutex<HashMap<usize, DiscoveryFilter>>>,
+    passive_discovery_sessions: Arc<Mutex<HashSet<usize>>>,
+    undiscovered_devices: Arc<Mutex<Vec<UndiscoveredDevice>>>,
+    discovery_started_at: Arc<Mutex<Option<Duration>>>,
+    discovery_timers: Arc<Mutex<Vec<TimerId>>>,
+    clock: FakeClock,
+    discoverable_timeout_timer: Arc<Mutex<AdapterTimeout>>,
+    pairable_timeout_timer: Arc<Mutex<AdapterTimeout>>,
+    faults: FaultInjector,
+    latencies: LatencyInjector,
 }
 
 impl FakeBluetoothAdapter {
+    /// A device belongs to the adapter it was created with, so `devices` must
+    /// be empty, and only discovery sessions turn discovery on, so
+    /// `is_discovering` must be false; otherwise this fails with
+    /// `InvalidArguments`. Use `FakeBluetoothAdapterBuilder::device` to create
+    /// devices with the adapter.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(id: String,
                is_present: bool,
                is_powered: bool,
                can_start_discovery: bool,
                can_stop_discovery: bool,
                devices: Vec<Arc<FakeBluetoothDevice>>,
-               ad_datas: Vec<String>,
+               ad_datas: Vec<AdvertisingData>,
                address: String,
                name: String,
                alias: String,
@@ -46,50 +87,36 @@ impl FakeBluetoothAdapter {
                is_discovering: bool,
                uuids: Vec<String>,
                modalias: String)
-               -> Arc<FakeBluetoothAdapter> {
-        Arc::new(FakeBluetoothAdapter {
-            id: Arc::new(Mutex::new(id)),
-            is_present: Arc::new(Mutex::new(is_present)),
-            is_powered: Arc::new(Mutex::new(is_powered)),
-            can_start_discovery: Arc::new(Mutex::new(can_start_discovery)),
-            can_stop_discovery: Arc::new(Mutex::new(can_stop_discovery)),
-            devices: Arc::new(Mutex::new(devices)),
-            ad_datas: Arc::new(Mutex::new(ad_datas)),
-            address: Arc::new(Mutex::new(address)),
-            name: Arc::new(Mutex::new(name)),
-            alias: Arc::new(Mutex::new(alias)),
-            class: Arc::new(Mutex::new(class)),
-            is_discoverable: Arc::new(Mutex::new(is_discoverable)),
-            is_pairable: Arc::new(Mutex::new(is_pairable)),
-            pairable_timeout: Arc::new(Mutex::new(pairable_timeout)),
-            discoverable_timeout: Arc::new(Mutex::new(discoverable_timeout)),
-            is_discovering: Arc::new(Mutex::new(is_discovering)),
-            uuids: Arc::new(Mutex::new(uuids)),
-            modalias: Arc::new(Mutex::new(modalias)),
-        })
+               -> Result<Arc<FakeBluetoothAdapter>, BlurmockError> {
+        if !devices.is_empty() || is_discovering {
+            return Err(BlurmockError::InvalidArguments);
+        }
+        Ok(FakeBluetoothAdapter::builder()
+            .id(id)
+            .present(is_present)
+            .powered(is_powered)
+            .can_start_discovery(can_start_discovery)
+            .can_stop_discovery(can_stop_discovery)
+            .ad_datas(ad_datas)
+            .address(address)
+            .name(name)
+            .alias(alias)
+            .class(class)
+            .discoverable(is_discoverable)
+            .pairable(is_pairable)
+            .pairable_timeout(pairable_timeout)
+            .discoverable_timeout(discoverable_timeout)
+            .uuids(uuids)
+            .modalias(modalias)
+            .build())
     }
 
     pub fn new_empty() -> Arc<FakeBluetoothAdapter> {
-        FakeBluetoothAdapter::new(
-            /*id*/ String::new(),
-            /*is_present*/ true,
-            /*is_powered*/ false,
-            /*can_start_discovery*/ true,
-            /*can_stop_discovery*/ true,
-            /*devices*/ vec![],
-            /*ad_datas*/ vec![],
-            /*address*/ String::new(),
-            /*name*/ String::new(),
-            /*alias*/ String::new(),
-            /*class*/ 0,
-            /*is_discoverable*/ false,
-            /*is_pairable*/ false,
-            /*pairable_timeout*/ 0,
-            /*discoverable_timeout*/ 0,
-            /*is_discovering*/ false,
-            /*uuids*/ vec![],
-            /*modalias*/ String::new(),
-        )
+        FakeBluetoothAdapter::builder().build()
+    }
+
+    pub fn builder() -> FakeBluetoothAdapterBuilder {
+        FakeBluetoothAdapterBuilder::new()
     }
 
     make_getter!(get_id, id);
@@ -98,11 +125,47 @@ impl FakeBluetoothAdapter {
 
     make_getter!(is_present);
 
-    make_setter!(set_present, is_present, bool);
+    /// Removing the adapter powers it off and drops every device that is
+    /// not paired.
+    pub fn set_present(&self, is_present: bool) -> Result<(), BlurmockError> {
+        if !is_present {
+            self.set_powered(false)?;
+            for device in self.get_devices()? {
+                if !device.is_paired()? {
+                    self.remove_device(device.get_id())?;
+                }
+            }
+        }
+        self.replace_flag(&self.is_present, "is_present", is_present)
+    }
 
     make_getter!(is_powered);
 
-    make_setter!(set_powered, is_powered, bool);
+    /// Powering off disconnects every device and stops all discovery
+    /// sessions. Until the adapter is powered on again, connecting and GATT
+    /// operations fail with `AdapterNotPowered`.
+    pub fn set_powered(&self, is_powered: bool) -> Result<(), BlurmockError> {
+        if !is_powered && self.is_powered()? {
+            for device in self.get_devices()? {
+                if device.is_connected()? {
+                    device.drop_connection()?;
+                }
+            }
+            self.stop_all_discovery_sessions()?;
+        }
+        self.replace_flag(&self.is_powered, "is_powered", is_powered)
+    }
+
+    /// Fails unless the adapter is present and powered.
+    pub fn check_powered(&self) -> Result<(), BlurmockError> {
+        if !self.is_present()? {
+            return Err(BlurmockError::AdapterNotPresent);
+        }
+        if !self.is_powered()? {
+            return Err(BlurmockError::AdapterNotPowered);
+        }
+        Ok(())
+    }
 
     make_getter!(get_can_start_discovery, can_start_discovery, bool);
 
@@ -114,9 +177,9 @@ impl FakeBluetoothAdapter {
 
     make_getter!(get_devices, devices, Vec<Arc<FakeBluetoothDevice>>);
 
-    make_getter!(get_ad_datas, ad_datas, Vec<String>);
+    make_getter!(get_ad_datas, ad_datas, Vec<AdvertisingData>);
 
-    make_setter!(set_ad_datas, ad_datas, Vec<String>);
+    make_setter!(set_ad_datas, ad_datas, Vec<AdvertisingData>);
 
     make_getter!(get_address, address, String);
 
@@ -136,23 +199,141 @@ impl FakeBluetoothAdapter {
 
     make_getter!(is_discoverable);
 
-    make_setter!(set_discoverable, is_discoverable, bool);
+    /// Turning discoverable on starts the `discoverable_timeout`, after which
+    /// the adapter turns it off again.
+    pub fn set_discoverable(&self, is_discoverable: bool) -> Result<(), BlurmockError> {
+        self.replace_flag(&self.is_discoverable, "is_discoverable", is_discoverable)?;
+        self.restart_discoverable_timeout()
+    }
 
     make_getter!(is_pairable);
 
-    make_setter!(set_pairable, is_pairable, bool);
+    /// Turning pairable on starts the `pairable_timeout`, after which the
+    /// adapter turns it off again.
+    pub fn set_pairable(&self, is_pairable: bool) -> Result<(), BlurmockError> {
+        self.replace_flag(&self.is_pairable, "is_pairable", is_pairable)?;
+        self.restart_pairable_timeout()
+    }
 
     make_getter!(get_pairable_timeout, pairable_timeout, u32);
 
-    make_setter!(set_pairable_timeout, pairable_timeout, u32);
+    /// The time in seconds the adapter stays pairable, 0 meaning forever. As
+    /// in BlueZ, changing it while pairable restarts the countdown.
+    pub fn set_pairable_timeout(&self, pairable_timeout: u32) -> Result<(), BlurmockError> {
+        let cloned = self.pairable_timeout.clone();
+        let mut value_to_change = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        let old_value = ::std::mem::replace(&mut *value_to_change, pairable_timeout);
+        drop(value_to_change);
+        if old_value != pairable_timeout {
+            self.emit_property_changed("pairable_timeout", old_value.into(), pairable_timeout.into());
+        }
+        self.restart_pairable_timeout()
+    }
 
     make_getter!(get_discoverable_timeout, discoverable_timeout, u32);
 
-    make_setter!(set_discoverable_timeout, discoverable_timeout, u32);
+    /// The time in seconds the adapter stays discoverable, 0 meaning forever.
+    /// As in BlueZ, changing it while discoverable restarts the countdown.
+    pub fn set_discoverable_timeout(&self, discoverable_timeout: u32) -> Result<(), BlurmockError> {
+        let cloned = self.discoverable_timeout.clone();
+        let mut value_to_change = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        let old_value = ::std::mem::replace(&mut *value_to_change, discoverable_timeout);
+        drop(value_to_change);
+        if old_value != discoverable_timeout {
+            self.emit_property_changed("discoverable_timeout", old_value.into(), discoverable_timeout.into());
+        }
+        self.restart_discoverable_timeout()
+    }
+
+    fn replace_flag(&self, flag: &Arc<Mutex<bool>>, property: &'static str, value: bool) -> Result<(), BlurmockError> {
+        let mut value_to_change = match flag.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        let old_value = ::std::mem::replace(&mut *value_to_change, value);
+        drop(value_to_change);
+        if old_value != value {
+            self.emit_property_changed(property, old_value.into(), value.into());
+        }
+        Ok(())
+    }
+
+    fn restart_discoverable_timeout(&self) -> Result<(), BlurmockError> {
+        let timeout = if self.is_discoverable()? { self.get_discoverable_timeout()? } else { 0 };
+        self.restart_timeout(&self.discoverable_timeout_timer, timeout, |adapter| {
+            adapter.replace_flag(&adapter.is_discoverable, "is_discoverable", false)
+        })
+    }
+
+    fn restart_pairable_timeout(&self) -> Result<(), BlurmockError> {
+        let timeout = if self.is_pairable()? { self.get_pairable_timeout()? } else { 0 };
+        self.restart_timeout(&self.pairable_timeout_timer, timeout, |adapter| {
+            adapter.replace_flag(&adapter.is_pairable, "is_pairable", false)
+        })
+    }
+
+    // Cancels the running countdown and, unless `timeout` is 0, starts a new
+    // one that calls `expire` after `timeout` seconds.
+    fn restart_timeout<F>(&self, slot: &Arc<Mutex<AdapterTimeout>>, timeout: u32, expire: F) -> Result<(), BlurmockError>
+        where F: FnOnce(&FakeBluetoothAdapter) -> Result<(), BlurmockError> + Send + 'static
+    {
+        let mut armed = match slot.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        if let Some(timer) = armed.timer.take() {
+            self.clock.cancel(timer)?;
+        }
+        armed.generation += 1;
+        let generation = armed.generation;
+        drop(armed);
+        if timeout == 0 {
+            return Ok(());
+        }
+
+        let adapter = self.clone();
+        let callback_slot = slot.clone();
+        let timer = self.clock.schedule(Duration::from_secs(u64::from(timeout)), move || {
+            match callback_slot.lock() {
+                Ok(mut armed) if armed.generation == generation => armed.timer = None,
+                _ => return,
+            }
+            let _ = expire(&adapter);
+        })?;
+
+        let mut armed = match slot.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        if armed.generation == generation {
+            armed.timer = Some(timer);
+        }
+        Ok(())
+    }
 
     make_getter!(is_discovering);
 
-    make_setter!(set_discovering, is_discovering, bool);
+    // Only the discovery sessions turn discovery on and off, see
+    // `add_discovery_session`.
+    pub(crate) fn set_discovering(&self, value: bool) -> Result<(), BlurmockError> {
+        let cloned = self.is_discovering.clone();
+        let mut value_to_change = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        let old_value = ::std::mem::replace(&mut *value_to_change, value);
+        drop(value_to_change);
+        if old_value != value {
+            self.emit_property_changed("is_discovering", old_value.into(), value.into());
+        }
+        Ok(())
+    }
 
     make_getter!(get_uuids, uuids, Vec<String>);
 
@@ -160,19 +341,19 @@ impl FakeBluetoothAdapter {
 
     make_setter!(set_modalias, modalias, String);
 
-    pub fn get_device(&self, id: String) -> Result<Arc<FakeBluetoothDevice>, Box<Error>> {
-        let devices = try!(self.get_devices());
+    pub fn get_device(&self, id: String) -> Result<Arc<FakeBluetoothDevice>, BlurmockError> {
+        let devices = self.get_devices()?;
         for device in devices {
             let device_id = device.get_id();
             if device_id == id {
                 return Ok(device);
             }
         }
-        Err(Box::from("No device exists with the given id."))
+        Err(BlurmockError::NotFound { kind: ObjectKind::Device, id: Some(id) })
     }
 
-    pub fn get_device_list(&self) -> Result<Vec<String>, Box<Error>> {
-        let devices = try!(self.get_devices());
+    pub fn get_device_list(&self) -> Result<Vec<String>, BlurmockError> {
+        let devices = self.get_devices()?;
         let mut ids = vec![];
         for device in &devices {
             let id = device.get_id();
@@ -181,80 +362,896 @@ impl FakeBluetoothAdapter {
         Ok(ids)
     }
 
-    pub fn get_first_device(&self) -> Result<Arc<FakeBluetoothDevice>, Box<Error>> {
-        let devices = try!(self.get_devices());
+    pub fn get_first_device(&self) -> Result<Arc<FakeBluetoothDevice>, BlurmockError> {
+        let devices = self.get_devices()?;
         if devices.is_empty() {
-            return Err(Box::from("No device found."))
+            return Err(BlurmockError::NotFound { kind: ObjectKind::Device, id: None });
         }
         Ok(devices[0].clone())
     }
 
-    pub fn add_device(&self, device: Arc<FakeBluetoothDevice>) -> Result<(), Box<Error>> {
+    pub fn add_device(&self, device: Arc<FakeBluetoothDevice>) -> Result<(), BlurmockError> {
         let cloned = self.devices.clone();
         let mut devices = match cloned.lock() {
             Ok(guard) => guard,
-            Err(_) => return Err(Box::from("Could not get the value.")),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
         };
-        Ok(devices.push(device))
+        let device_id = device.get_id();
+        devices.push(device);
+        drop(devices);
+        self.emit_event(FakeBluetoothEvent::DeviceAdded {
+            adapter_id: self.get_id(),
+            device_id,
+        });
+        Ok(())
     }
 
-    pub fn remove_device(&self, id: String) -> Result<(), Box<Error>> {
+    pub fn remove_device(&self, id: String) -> Result<(), BlurmockError> {
         let cloned = self.devices.clone();
         let mut devices = match cloned.lock() {
             Ok(guard) => guard,
-            Err(_) => return Err(Box::from("Could not get the value.")),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
         };
-        Ok(devices.retain(|d| d.get_id() != id))
+        let count = devices.len();
+        devices.retain(|d| d.get_id() != id);
+        let removed = devices.len() != count;
+        drop(devices);
+        if removed {
+            self.emit_event(FakeBluetoothEvent::DeviceRemoved {
+                adapter_id: self.get_id(),
+                device_id: id,
+            });
+        }
+        Ok(())
     }
 
-    pub fn get_first_ad_data(&self) -> Result<String, Box<Error>> {
-        let ad_datas = try!(self.get_ad_datas());
+    pub fn get_first_ad_data(&self) -> Result<AdvertisingData, BlurmockError> {
+        let ad_datas = self.get_ad_datas()?;
         if ad_datas.is_empty() {
-            return Err(Box::from("No ad_data found."))
+            return Err(BlurmockError::NotFound { kind: ObjectKind::AdData, id: None });
         }
         Ok(ad_datas[0].clone())
     }
 
-     pub fn create_discovery_session(&self) -> Result<FakeBluetoothDiscoverySession, Box<Error>> {
+    pub fn device<S: Into<String>>(&self, id: S) -> FakeBluetoothDeviceBuilder {
+        FakeBluetoothDeviceBuilder::new(Arc::new(self.clone()), id)
+    }
+
+    pub fn create_discovery_session(&self) -> Result<FakeBluetoothDiscoverySession, BlurmockError> {
         FakeBluetoothDiscoverySession::create_session(Arc::new(self.clone()))
     }
 
-    pub fn get_modalias(&self) ->  Result<(String, u32, u32, u32), Box<Error>> {
+    /// Returns the number of discovery sessions that are currently started.
+    pub fn get_discovery_session_count(&self) -> Result<usize, BlurmockError> {
+        let cloned = self.discovery_sessions.clone();
+        let discovery_sessions = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        Ok(discovery_sessions.len())
+    }
+
+    /// Returns the filter the adapter scans with: the filters of all started
+    /// sessions merged the way BlueZ merges `SetDiscoveryFilter` calls.
+    pub fn get_discovery_filter(&self) -> Result<DiscoveryFilter, BlurmockError> {
+        let cloned = self.discovery_sessions.clone();
+        let discovery_sessions = match cloned.lock() {
+            Ok(guard) => guard.clone(),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        let cloned = self.discovery_filters.clone();
+        let discovery_filters = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        let filters: Vec<Option<&DiscoveryFilter>> = discovery_sessions.iter()
+                                                                      .map(|id| discovery_filters.get(id))
+                                                                      .collect();
+        Ok(DiscoveryFilter::merge(&filters))
+    }
+
+    pub(crate) fn get_session_discovery_filter(&self, session_id: usize)
+                                               -> Result<Option<DiscoveryFilter>, BlurmockError> {
+        let cloned = self.discovery_filters.clone();
+        let discovery_filters = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        Ok(discovery_filters.get(&session_id).cloned())
+    }
+
+    pub(crate) fn set_session_discovery_filter(&self,
+                                               session_id: usize,
+                                               filter: Option<DiscoveryFilter>)
+                                               -> Result<(), BlurmockError> {
+        let cloned = self.discovery_filters.clone();
+        let mut discovery_filters = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        match filter {
+            Some(filter) => discovery_filters.insert(session_id, filter),
+            None => discovery_filters.remove(&session_id),
+        };
+        Ok(())
+    }
+
+    pub(crate) fn allocate_discovery_session_id(&self) -> Result<usize, BlurmockError> {
+        let cloned = self.next_discovery_session_id.clone();
+        let mut next_id = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        *next_id += 1;
+        Ok(*next_id)
+    }
+
+    pub(crate) fn is_discovery_session_active(&self, session_id: usize) -> Result<bool, BlurmockError> {
+        let cloned = self.discovery_sessions.clone();
+        let discovery_sessions = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        Ok(discovery_sessions.contains(&session_id))
+    }
+
+    // Discovery is on while at least one session is started: the first
+    // session turns it on and the last one to stop turns it off.
+    pub(crate) fn add_discovery_session(&self, session_id: usize) -> Result<(), BlurmockError> {
+        let cloned = self.discovery_sessions.clone();
+        let mut discovery_sessions = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        let inserted = discovery_sessions.insert(session_id);
+        let is_first = discovery_sessions.len() == 1;
+        drop(discovery_sessions);
+        if inserted && is_first {
+            self.set_discovering(true)?;
+            self.set_discovery_started_at(Some(self.clock.now()?))?;
+            for (device, schedule) in self.get_undiscovered_devices()? {
+                self.schedule_device_discovery(device.get_id(), schedule)?;
+            }
+        }
+        if inserted {
+            self.refresh_advertising_data()?;
+        }
+        Ok(())
+    }
+
+    fn stop_all_discovery_sessions(&self) -> Result<(), BlurmockError> {
+        let cloned = self.discovery_sessions.clone();
+        let mut discovery_sessions = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        let was_discovering = !discovery_sessions.is_empty();
+        discovery_sessions.clear();
+        drop(discovery_sessions);
+        if was_discovering {
+            self.set_discovery_started_at(None)?;
+            self.cancel_discovery_timers()?;
+            self.set_discovering(false)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn set_session_active_scanning(&self, session_id: usize, active_scanning: bool) -> Result<(), BlurmockError> {
+        let cloned = self.passive_discovery_sessions.clone();
+        let mut passive_discovery_sessions = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        if active_scanning {
+            passive_discovery_sessions.remove(&session_id);
+        } else {
+            passive_discovery_sessions.insert(session_id);
+        }
+        drop(passive_discovery_sessions);
+        if self.is_discovery_session_active(session_id)? {
+            self.refresh_advertising_data()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn is_session_active_scanning(&self, session_id: usize) -> Result<bool, BlurmockError> {
+        let cloned = self.passive_discovery_sessions.clone();
+        let passive_discovery_sessions = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        Ok(!passive_discovery_sessions.contains(&session_id))
+    }
+
+    /// Whether the adapter sends scan requests: true while at least one
+    /// started discovery session scans actively.
+    pub fn is_active_scanning(&self) -> Result<bool, BlurmockError> {
+        let cloned = self.discovery_sessions.clone();
+        let discovery_sessions = match cloned.lock() {
+            Ok(guard) => guard.deref().clone(),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        for session_id in discovery_sessions {
+            if self.is_session_active_scanning(session_id)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // Lets every device show what the current scan receives of its
+    // advertisement and scan response.
+    pub(crate) fn refresh_advertising_data(&self) -> Result<(), BlurmockError> {
+        if !self.is_discovering()? {
+            return Ok(());
+        }
+        let active_scanning = self.is_active_scanning()?;
+        for device in self.get_devices()? {
+            device.receive_advertising_data(active_scanning)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn remove_discovery_session(&self, session_id: usize) -> Result<(), BlurmockError> {
+        let cloned = self.discovery_sessions.clone();
+        let mut discovery_sessions = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        let removed = discovery_sessions.remove(&session_id);
+        let is_last = discovery_sessions.is_empty();
+        drop(discovery_sessions);
+        if removed && is_last {
+            self.set_discovery_started_at(None)?;
+            self.cancel_discovery_timers()?;
+            self.set_discovering(false)?;
+        }
+        Ok(())
+    }
+
+    /// Registers a device that stays hidden until a discovery session finds
+    /// it according to `schedule`. It is then added to the adapter, raising
+    /// `DeviceAdded`, and stays there even if discovery stops.
+    pub fn add_undiscovered_device(&self,
+                                   device: Arc<FakeBluetoothDevice>,
+                                   schedule: DiscoverySchedule)
+                                   -> Result<(), BlurmockError> {
+        let device_id = device.get_id();
+        let cloned = self.undiscovered_devices.clone();
+        let mut undiscovered_devices = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        undiscovered_devices.push((device, schedule));
+        drop(undiscovered_devices);
+        self.schedule_device_discovery(device_id, schedule)
+    }
+
+    pub fn get_undiscovered_devices(&self) -> Result<Vec<UndiscoveredDevice>, BlurmockError> {
+        let cloned = self.undiscovered_devices.clone();
+        let undiscovered_devices = match cloned.lock() {
+            Ok(guard) => guard.deref().clone(),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        Ok(undiscovered_devices)
+    }
+
+    pub(crate) fn find_undiscovered_device(&self, id: &str) -> Option<Arc<FakeBluetoothDevice>> {
+        self.get_undiscovered_devices()
+            .ok()
+            .and_then(|devices| devices.into_iter().map(|(d, _)| d).find(|d| d.get_id() == id))
+    }
+
+    /// Finds the next device scheduled with `DiscoverySchedule::OnAdvance` and
+    /// returns its id, or `None` if there is no such device left.
+    pub fn advance_discovery(&self) -> Result<Option<String>, BlurmockError> {
+        if !self.is_discovering()? {
+            return Err(BlurmockError::NotDiscovering);
+        }
+        let next_device = self.get_undiscovered_devices()?
+                              .into_iter()
+                              .find(|&(_, schedule)| schedule == DiscoverySchedule::OnAdvance);
+        match next_device {
+            Some((device, _)) => {
+                let device_id = device.get_id();
+                self.discover_device(&device_id)?;
+                Ok(Some(device_id))
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn discover_device(&self, id: &str) -> Result<(), BlurmockError> {
+        let cloned = self.undiscovered_devices.clone();
+        let mut undiscovered_devices = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        let index = undiscovered_devices.iter().position(|(d, _)| d.get_id() == id);
+        let device = match index {
+            Some(index) => undiscovered_devices.remove(index).0,
+            None => return Ok(()),
+        };
+        drop(undiscovered_devices);
+        self.add_device(device.clone())?;
+        device.receive_advertising_data(self.is_active_scanning()?)
+    }
+
+    /// Simulates the adapter receiving an advertisement from the device: the
+    /// device properties are updated from the packet, then
+    /// `AdvertisementReceived` is sent. An undiscovered device is found by
+    /// its advertisement while the adapter is discovering. An adapter that is
+    /// not present or not powered receives nothing and fails like
+    /// `check_powered`.
+    pub fn simulate_advertisement(&self, device_id: String, packet: AdvertisementPacket) -> Result<(), BlurmockError> {
+        self.check_powered()?;
+        if self.is_discovering()? && self.find_undiscovered_device(&device_id).is_some() {
+            self.discover_device(&device_id)?;
+        }
+        let device = self.get_device(device_id.clone())?;
+        device.receive_advertisement(&packet)?;
+        self.emit_event(FakeBluetoothEvent::AdvertisementReceived {
+            adapter_id: self.get_id(),
+            device_id,
+            packet: Box::new(packet),
+        });
+        Ok(())
+    }
+
+    fn set_discovery_started_at(&self, started_at: Option<Duration>) -> Result<(), BlurmockError> {
+        let cloned = self.discovery_started_at.clone();
+        let mut discovery_started_at = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        *discovery_started_at = started_at;
+        Ok(())
+    }
+
+    fn get_discovery_started_at(&self) -> Result<Option<Duration>, BlurmockError> {
+        let cloned = self.discovery_started_at.clone();
+        let discovery_started_at = match cloned.lock() {
+            Ok(guard) => *guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        Ok(discovery_started_at)
+    }
+
+    // Finds the device once discovery has been running long enough. The timers
+    // are cancelled when discovery stops.
+    fn schedule_device_discovery(&self, device_id: String, schedule: DiscoverySchedule) -> Result<(), BlurmockError> {
+        let delay = match schedule {
+            DiscoverySchedule::AfterDiscovering(delay) => delay,
+            DiscoverySchedule::OnAdvance => return Ok(()),
+        };
+        let started_at = match self.get_discovery_started_at()? {
+            Some(started_at) => started_at,
+            None => return Ok(()),
+        };
+        let elapsed = self.clock.now()? - started_at;
+        let remaining = if delay > elapsed { delay - elapsed } else { Duration::from_secs(0) };
+        let adapter = self.clone();
+        let timer = self.clock.schedule(remaining, move || {
+            let _ = adapter.discover_device(&device_id);
+        })?;
+        let cloned = self.discovery_timers.clone();
+        let mut discovery_timers = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        discovery_timers.push(timer);
+        Ok(())
+    }
+
+    fn cancel_discovery_timers(&self) -> Result<(), BlurmockError> {
+        let cloned = self.discovery_timers.clone();
+        let timers = match cloned.lock() {
+            Ok(mut guard) => ::std::mem::take(&mut *guard),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        for timer in timers {
+            self.clock.cancel(timer)?;
+        }
+        Ok(())
+    }
+
+    /// Registers a fault rule for the operations of this adapter and of
+    /// everything below it.
+    pub fn inject_fault(&self, rule: FaultRule) -> Result<FaultId, BlurmockError> {
+        self.faults.add_rule(rule)
+    }
+
+    pub fn remove_fault(&self, id: FaultId) -> Result<bool, BlurmockError> {
+        self.faults.remove_rule(id)
+    }
+
+    pub fn clear_faults(&self) -> Result<(), BlurmockError> {
+        self.faults.clear()
+    }
+
+    pub fn get_fault_injector(&self) -> FaultInjector {
+        self.faults.clone()
+    }
+
+    /// Sets how long an operation of this adapter or of anything below it
+    /// takes, replacing the rule for the same operation and target. On a
+    /// manual clock the delayed call blocks until another thread advances
+    /// the clock past it, so a test that sets latency on a manual clock must
+    /// make the call from a second thread, see `FakeClock::sleeping_thread_count`.
+    pub fn set_latency(&self, rule: LatencyRule) -> Result<(), BlurmockError> {
+        self.latencies.set_rule(rule)
+    }
+
+    pub fn remove_latency(&self, operation: Operation, target: Option<String>) -> Result<bool, BlurmockError> {
+        self.latencies.remove_rule(operation, target)
+    }
+
+    pub fn clear_latencies(&self) -> Result<(), BlurmockError> {
+        self.latencies.clear()
+    }
+
+    pub fn get_latency_injector(&self) -> LatencyInjector {
+        self.latencies.clone()
+    }
+
+    // Waits for the latency of the operation, then applies the fault rules.
+    pub(crate) fn begin_operation(&self, operation: Operation, object_id: &str) -> Result<(), BlurmockError> {
+        self.clock.sleep(self.latencies.delay(operation, object_id)?)?;
+        self.faults.check(operation, object_id)
+    }
+
+    /// The clock shared by the adapter and everything below it.
+    pub fn get_clock(&self) -> FakeClock {
+        self.clock.clone()
+    }
+
+    /// Takes a deep copy of the adapter and of everything below it, including
+    /// the fault and latency rules with their call counts and random state.
+    /// Event subscribers, discovery sessions and
+    /// notification receivers are not part of the copy, so the copy is never
+    /// discovering and none of its characteristics is notifying: devices
+    /// waiting to be discovered are found once a new session starts.
+    pub fn snapshot(&self) -> Result<AdapterSnapshot, BlurmockError> {
+        let adapter = FakeBluetoothAdapter::builder().clock(self.clock.clone()).build();
+        adapter.copy_state(self)?;
+        Ok(AdapterSnapshot::new(adapter))
+    }
+
+    /// Resets the adapter tree to the state of `snapshot` without sending any
+    /// event. The devices are replaced by new objects, so handles to the old
+    /// ones have to be looked up again. Running discovery sessions are
+    /// forgotten and discovery is turned off.
+    pub fn restore(&self, snapshot: &AdapterSnapshot) -> Result<(), BlurmockError> {
+        self.copy_state(snapshot.get_adapter())?;
+        self.resume_timers()
+    }
+
+    /// Creates a new adapter tree in the current state of this one, sharing
+    /// nothing with it but the clock.
+    pub fn fork(&self) -> Result<Arc<FakeBluetoothAdapter>, BlurmockError> {
+        let adapter = FakeBluetoothAdapter::builder().clock(self.clock.clone()).build();
+        adapter.copy_state(self)?;
+        adapter.resume_timers()?;
+        Ok(adapter)
+    }
+
+    // Gives this adapter the state of `source`, with copies of its devices.
+    // No timer is armed, so that snapshots stay as they were taken.
+    fn copy_state(&self, source: &FakeBluetoothAdapter) -> Result<(), BlurmockError> {
+        restore_value(&self.id, &source.id)?;
+        restore_value(&self.is_present, &source.is_present)?;
+        restore_value(&self.is_powered, &source.is_powered)?;
+        restore_value(&self.can_start_discovery, &source.can_start_discovery)?;
+        restore_value(&self.can_stop_discovery, &source.can_stop_discovery)?;
+        restore_value(&self.ad_datas, &source.ad_datas)?;
+        restore_value(&self.address, &source.address)?;
+        restore_value(&self.name, &source.name)?;
+        restore_value(&self.alias, &source.alias)?;
+        restore_value(&self.class, &source.class)?;
+        restore_value(&self.is_discoverable, &source.is_discoverable)?;
+        restore_value(&self.is_pairable, &source.is_pairable)?;
+        restore_value(&self.pairable_timeout, &source.pairable_timeout)?;
+        restore_value(&self.discoverable_timeout, &source.discoverable_timeout)?;
+        restore_value(&self.uuids, &source.uuids)?;
+        restore_value(&self.modalias, &source.modalias)?;
+
+        self.cancel_discovery_timers()?;
+        match self.discovery_sessions.lock() {
+            Ok(mut discovery_sessions) => discovery_sessions.clear(),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        }
+        match self.discovery_filters.lock() {
+            Ok(mut discovery_filters) => discovery_filters.clear(),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        }
+        match self.passive_discovery_sessions.lock() {
+            Ok(mut passive_discovery_sessions) => passive_discovery_sessions.clear(),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        }
+        // Discovery belongs to the sessions, which are not copied.
+        match self.is_discovering.lock() {
+            Ok(mut is_discovering) => *is_discovering = false,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        }
+        self.set_discovery_started_at(None)?;
+
+        // Clones share their state, so the devices can belong to this one.
+        let adapter = Arc::new(self.clone());
+        let devices = source.get_devices()?
+                            .iter()
+                            .map(|device| device.deep_copy(adapter.clone()))
+                            .collect::<Result<Vec<_>, _>>()?;
+        let mut undiscovered_devices = vec![];
+        for (device, schedule) in source.get_undiscovered_devices()? {
+            undiscovered_devices.push((device.deep_copy(adapter.clone())?, schedule));
+        }
+        match self.devices.lock() {
+            Ok(mut guard) => *guard = devices,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        }
+        match self.undiscovered_devices.lock() {
+            Ok(mut guard) => *guard = undiscovered_devices,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        }
+
+        self.faults.copy_from(&source.faults)?;
+        self.latencies.copy_from(&source.latencies)?;
+        Ok(())
+    }
+
+    // Arms the discoverable and pairable timeouts after `copy_state`.
+    fn resume_timers(&self) -> Result<(), BlurmockError> {
+        self.restart_discoverable_timeout()?;
+        self.restart_pairable_timeout()
+    }
+
+    /// Returns a receiver for every event raised on this adapter and on the
+    /// devices, services, characteristics and descriptors below it.
+    pub fn subscribe(&self) -> Result<Receiver<FakeBluetoothEvent>, BlurmockError> {
+        let (sender, receiver) = mpsc::channel();
+        let cloned = self.subscribers.clone();
+        let mut subscribers = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        subscribers.push(sender);
+        Ok(receiver)
+    }
+
+    pub(crate) fn emit_event(&self, event: FakeBluetoothEvent) {
+        let cloned = self.subscribers.clone();
+        let mut subscribers = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return,
+        };
+        subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    fn emit_property_changed(&self, property: &'static str, old: PropertyValue, new: PropertyValue) {
+        self.emit_event(FakeBluetoothEvent::PropertyChanged {
+            kind: ObjectKind::Adapter,
+            object_id: self.get_id(),
+            property,
+            old,
+            new,
+        });
+    }
+
+    pub fn get_modalias(&self) -> Result<Modalias, BlurmockError> {
         let cloned = self.modalias.clone();
         let modalias = match cloned.lock() {
             Ok(guard) => guard.deref().clone(),
-            Err(_) => return Err(Box::from("Could not get the value.")),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
         };
-        let ids: Vec<&str> = modalias.split(":").collect();
+        Ok(modalias.parse::<Modalias>()?)
+    }
 
-        let source = String::from(ids[0]);
-        let vendor = hex::decode(&ids[1][1..5]).unwrap();
-        let product = hex::decode(&ids[1][6..10]).unwrap();
-        let device = hex::decode(&ids[1][11..15]).unwrap();
+    // The modalias as stored, which scenarios save without parsing it.
+    #[cfg(feature = "scenario")]
+    pub(crate) fn get_raw_modalias(&self) -> Result<String, BlurmockError> {
+        match self.modalias.lock() {
+            Ok(guard) => Ok(guard.deref().clone()),
+            Err(_) => Err(BlurmockError::LockPoisoned),
+        }
+    }
+
+    pub fn set_modalias_ids(&self,
+                            source: ModaliasSource,
+                            vendor: u32,
+                            product: u32,
+                            version: u32)
+                            -> Result<(), BlurmockError> {
+        self.set_modalias(Modalias::new(source, vendor, product, version).to_string())
+    }
+
+    pub fn get_vendor_id_source(&self) -> Result<String, BlurmockError> {
+        Ok(self.get_modalias()?.source.to_string())
+    }
+
+    pub fn get_vendor_id(&self) -> Result<u32, BlurmockError> {
+        Ok(self.get_modalias()?.vendor)
+    }
+
+    pub fn get_product_id(&self) -> Result<u32, BlurmockError> {
+        Ok(self.get_modalias()?.product)
+    }
+
+    pub fn get_device_id(&self) -> Result<u32, BlurmockError> {
+        Ok(self.get_modalias()?.version)
+    }
+}
+
+// A device for `FakeBluetoothAdapterBuilder::build` to create on the new
+// adapter.
+#[derive(Clone)]
+struct DeviceSetup {
+    id: String,
+    build: Arc<dyn Fn(FakeBluetoothDeviceBuilder) -> FakeBluetoothDeviceBuilder + Send + Sync>,
+}
+
+impl fmt::Debug for DeviceSetup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DeviceSetup").field("id", &self.id).finish()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FakeBluetoothAdapterBuilder {
+    id: String,
+    is_present: bool,
+    is_powered: bool,
+    can_start_discovery: bool,
+    can_stop_discovery: bool,
+    devices: Vec<DeviceSetup>,
+    ad_datas: Vec<AdvertisingData>,
+    address: String,
+    name: String,
+    alias: String,
+    class: u32,
+    is_discoverable: bool,
+    is_pairable: bool,
+    pairable_timeout: u32,
+    discoverable_timeout: u32,
+    uuids: Vec<String>,
+    modalias: String,
+    clock: FakeClock,
+}
+
+impl Default for FakeBluetoothAdapterBuilder {
+    fn default() -> FakeBluetoothAdapterBuilder {
+        FakeBluetoothAdapterBuilder {
+            id: String::new(),
+            is_present: true,
+            is_powered: false,
+            can_start_discovery: true,
+            can_stop_discovery: true,
+            devices: vec![],
+            ad_datas: vec![],
+            address: String::new(),
+            name: String::new(),
+            alias: String::new(),
+            class: 0,
+            is_discoverable: false,
+            is_pairable: false,
+            pairable_timeout: 0,
+            discoverable_timeout: 0,
+            uuids: vec![],
+            modalias: String::new(),
+            clock: FakeClock::real(),
+        }
+    }
+}
+
+impl FakeBluetoothAdapterBuilder {
+    pub fn new() -> FakeBluetoothAdapterBuilder {
+        FakeBluetoothAdapterBuilder::default()
+    }
+
+    pub fn id<S: Into<String>>(mut self, id: S) -> FakeBluetoothAdapterBuilder {
+        self.id = id.into();
+        self
+    }
+
+    pub fn present(mut self, is_present: bool) -> FakeBluetoothAdapterBuilder {
+        self.is_present = is_present;
+        self
+    }
+
+    pub fn powered(mut self, is_powered: bool) -> FakeBluetoothAdapterBuilder {
+        self.is_powered = is_powered;
+        self
+    }
+
+    pub fn can_start_discovery(mut self, can_start_discovery: bool) -> FakeBluetoothAdapterBuilder {
+        self.can_start_discovery = can_start_discovery;
+        self
+    }
 
-        Ok((source,
-        (vendor[0] as u32) * 16 * 16 + (vendor[1] as u32),
-        (product[0] as u32) * 16 * 16 + (product[1] as u32),
-        (device[0] as u32) * 16 * 16 + (device[1] as u32)))
+    pub fn can_stop_discovery(mut self, can_stop_discovery: bool) -> FakeBluetoothAdapterBuilder {
+        self.can_stop_discovery = can_stop_discovery;
+        self
     }
 
-    pub fn get_vendor_id_source(&self) -> Result<String, Box<Error>> {
-        let (vendor_id_source,_,_,_) = try!(self.get_modalias());
-        Ok(vendor_id_source)
+    /// Adds a device that `build` creates on the new adapter, configured by
+    /// `build` from a `FakeBluetoothDeviceBuilder`.
+    pub fn device<S, F>(mut self, id: S, build: F) -> FakeBluetoothAdapterBuilder
+        where S: Into<String>,
+              F: Fn(FakeBluetoothDeviceBuilder) -> FakeBluetoothDeviceBuilder + Send + Sync + 'static
+    {
+        self.devices.push(DeviceSetup {
+            id: id.into(),
+            build: Arc::new(build),
+        });
+        self
     }
 
-    pub fn get_vendor_id(&self) -> Result<u32, Box<Error>> {
-        let (_,vendor_id,_,_) = try!(self.get_modalias());
-        Ok(vendor_id)
+    pub fn ad_datas(mut self, ad_datas: Vec<AdvertisingData>) -> FakeBluetoothAdapterBuilder {
+        self.ad_datas = ad_datas;
+        self
     }
 
-    pub fn get_product_id(&self) -> Result<u32, Box<Error>> {
-        let (_,_,product_id,_) = try!(self.get_modalias());
-        Ok(product_id)
+    pub fn address<S: Into<String>>(mut self, address: S) -> FakeBluetoothAdapterBuilder {
+        self.address = address.into();
+        self
+    }
+
+    pub fn name<S: Into<String>>(mut self, name: S) -> FakeBluetoothAdapterBuilder {
+        self.name = name.into();
+        self
+    }
+
+    pub fn alias<S: Into<String>>(mut self, alias: S) -> FakeBluetoothAdapterBuilder {
+        self.alias = alias.into();
+        self
+    }
+
+    pub fn class(mut self, class: u32) -> FakeBluetoothAdapterBuilder {
+        self.class = class;
+        self
+    }
+
+    pub fn discoverable(mut self, is_discoverable: bool) -> FakeBluetoothAdapterBuilder {
+        self.is_discoverable = is_discoverable;
+        self
+    }
+
+    pub fn pairable(mut self, is_pairable: bool) -> FakeBluetoothAdapterBuilder {
+        self.is_pairable = is_pairable;
+        self
+    }
+
+    pub fn pairable_timeout(mut self, pairable_timeout: u32) -> FakeBluetoothAdapterBuilder {
+        self.pairable_timeout = pairable_timeout;
+        self
+    }
+
+    pub fn discoverable_timeout(mut self, discoverable_timeout: u32) -> FakeBluetoothAdapterBuilder {
+        self.discoverable_timeout = discoverable_timeout;
+        self
+    }
+
+    pub fn uuids(mut self, uuids: Vec<String>) -> FakeBluetoothAdapterBuilder {
+        self.uuids = uuids;
+        self
+    }
+
+    pub fn uuid<S: Into<String>>(mut self, uuid: S) -> FakeBluetoothAdapterBuilder {
+        self.uuids.push(uuid.into());
+        self
+    }
+
+    pub fn modalias<S: Into<String>>(mut self, modalias: S) -> FakeBluetoothAdapterBuilder {
+        self.modalias = modalias.into();
+        self
+    }
+
+    /// The clock of the adapter tree. Defaults to a real clock.
+    pub fn clock(mut self, clock: FakeClock) -> FakeBluetoothAdapterBuilder {
+        self.clock = clock;
+        self
+    }
+
+    pub fn build(self) -> Arc<FakeBluetoothAdapter> {
+        let adapter = Arc::new(FakeBluetoothAdapter {
+            id: Arc::new(Mutex::new(self.id)),
+            is_present: Arc::new(Mutex::new(self.is_present)),
+            is_powered: Arc::new(Mutex::new(self.is_powered)),
+            can_start_discovery: Arc::new(Mutex::new(self.can_start_discovery)),
+            can_stop_discovery: Arc::new(Mutex::new(self.can_stop_discovery)),
+            devices: Arc::new(Mutex::new(vec![])),
+            ad_datas: Arc::new(Mutex::new(self.ad_datas)),
+            address: Arc::new(Mutex::new(self.address)),
+            name: Arc::new(Mutex::new(self.name)),
+            alias: Arc::new(Mutex::new(self.alias)),
+            class: Arc::new(Mutex::new(self.class)),
+            is_discoverable: Arc::new(Mutex::new(self.is_discoverable)),
+            is_pairable: Arc::new(Mutex::new(self.is_pairable)),
+            pairable_timeout: Arc::new(Mutex::new(self.pairable_timeout)),
+            discoverable_timeout: Arc::new(Mutex::new(self.discoverable_timeout)),
+            is_discovering: Arc::new(Mutex::new(false)),
+            uuids: Arc::new(Mutex::new(self.uuids)),
+            modalias: Arc::new(Mutex::new(self.modalias)),
+            subscribers: Arc::new(Mutex::new(vec![])),
+            discovery_sessions: Arc::new(Mutex::new(HashSet::new())),
+            next_discovery_session_id: Arc::new(Mutex::new(0)),
+            discovery_filters: Arc::new(Mutex::new(HashMap::new())),
+            passive_discovery_sessions: Arc::new(Mutex::new(HashSet::new())),
+            undiscovered_devices: Arc::new(Mutex::new(vec![])),
+            discovery_started_at: Arc::new(Mutex::new(None)),
+            discovery_timers: Arc::new(Mutex::new(vec![])),
+            clock: self.clock,
+            discoverable_timeout_timer: Arc::new(Mutex::new(AdapterTimeout::default())),
+            pairable_timeout_timer: Arc::new(Mutex::new(AdapterTimeout::default())),
+            faults: FaultInjector::new(),
+            latencies: LatencyInjector::new(),
+        });
+        let _ = adapter.restart_discoverable_timeout();
+        let _ = adapter.restart_pairable_timeout();
+        for device in self.devices {
+            (device.build)(adapter.device(device.id)).build();
+        }
+        adapter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use advertising_data::AdvertisementPacket;
+    use error::BlurmockError;
+    use event::FakeBluetoothEvent;
+    use super::FakeBluetoothAdapter;
+
+    #[test]
+    fn new_rejects_devices_of_another_adapter() {
+        let other = FakeBluetoothAdapter::new_empty();
+        let device = other.device("device").build();
+        let adapter = FakeBluetoothAdapter::new(String::new(), true, false, true, true, vec![device], vec![],
+                                                String::new(), String::new(), String::new(), 0, false, false, 0, 0,
+                                                false, vec![], String::new());
+        assert_eq!(adapter.err(), Some(BlurmockError::InvalidArguments));
+    }
+
+    #[test]
+    fn new_rejects_discovering_without_a_session() {
+        let adapter = FakeBluetoothAdapter::new(String::new(), true, true, true, true, vec![], vec![], String::new(),
+                                                String::new(), String::new(), 0, false, false, 0, 0, true, vec![],
+                                                String::new());
+        assert_eq!(adapter.err(), Some(BlurmockError::InvalidArguments));
+    }
+
+    #[test]
+    fn advertisements_need_a_powered_adapter() {
+        let adapter = FakeBluetoothAdapter::builder().device("device", |d| d.paired(true)).build();
+        let events = adapter.subscribe().unwrap();
+        let device = adapter.get_device(String::from("device")).unwrap();
+        let packet = AdvertisementPacket { rssi: Some(-40), ..AdvertisementPacket::new() };
+
+        assert_eq!(adapter.simulate_advertisement(String::from("device"), packet.clone()),
+                   Err(BlurmockError::AdapterNotPowered));
+        adapter.set_powered(true).unwrap();
+        adapter.set_present(false).unwrap();
+        assert_eq!(adapter.simulate_advertisement(String::from("device"), packet.clone()),
+                   Err(BlurmockError::AdapterNotPresent));
+        assert_eq!(device.get_rssi(), Err(BlurmockError::NoValue));
+        assert!(!events.try_iter().any(|event| matches!(event, FakeBluetoothEvent::AdvertisementReceived { .. })));
+
+        adapter.set_present(true).unwrap();
+        adapter.set_powered(true).unwrap();
+        assert_eq!(adapter.simulate_advertisement(String::from("device"), packet), Ok(()));
+        assert_eq!(device.get_rssi(), Ok(-40));
     }
 
-    pub fn get_device_id(&self) -> Result<u32, Box<Error>> {
-        let (_,_,_,device_id) = try!(self.get_modalias());
-        Ok(device_id)
+    #[test]
+    fn builder_devices_belong_to_the_new_adapter() {
+        let adapter = FakeBluetoothAdapter::builder()
+            .id("hci0")
+            .device("first", |d| d.name("First"))
+            .device("second", |d| d.address("00:11:22:33:44:55"))
+            .build();
+        assert_eq!(adapter.get_device_list(), Ok(vec![String::from("first"), String::from("second")]));
+        let first = adapter.get_device(String::from("first")).unwrap();
+        assert_eq!(first.get_name(), Ok(String::from("First")));
+        adapter.set_name(String::from("renamed")).unwrap();
+        assert_eq!(first.get_adapter().unwrap().get_name(), Ok(String::from("renamed")));
     }
 }
@@ -1,8 +1,16 @@
+use clock::TimerId;
 use core::ops::Deref;
-use fake_descriptor::FakeBluetoothGATTDescriptor;
+use error::{BlurmockError, ObjectKind};
+use event::{FakeBluetoothEvent, PropertyValue};
+use fake_descriptor::{FakeBluetoothGATTDescriptor, FakeBluetoothGATTDescriptorBuilder};
 use fake_service::FakeBluetoothGATTService;
-use std::error::Error;
+use flags::CharacteristicFlags;
+use free_child_id;
+use operation::Operation;
+use snapshot::copy_value;
 use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
 
 #[derive(Clone, Debug)]
 pub struct FakeBluetoothGATTCharacteristic {
@@ -13,6 +21,7 @@ pub struct FakeBluetoothGATTCharacteristic {
     is_notifying: Arc<Mutex<bool>>,
     flags: Arc<Mutex<Vec<String>>>,
     gatt_descriptors: Arc<Mutex<Vec<Arc<FakeBluetoothGATTDescriptor>>>>,
+    notification_subscribers: Arc<Mutex<Vec<Sender<Vec<u8>>>>>,
 }
 
 impl FakeBluetoothGATTCharacteristic {
@@ -24,7 +33,7 @@ impl FakeBluetoothGATTCharacteristic {
                flags: Vec<String>,
                gatt_descriptors: Vec<Arc<FakeBluetoothGATTDescriptor>>)
                -> Arc<FakeBluetoothGATTCharacteristic> {
-        if let Ok(existing_characteristic) = service.get_gatt_characteristic(id.clone()) {
+        if let Ok(existing_characteristic) = service.find_gatt_characteristic(id.clone()) {
             return existing_characteristic;
         }
         let characteristic = Arc::new(FakeBluetoothGATTCharacteristic {
@@ -35,6 +44,7 @@ impl FakeBluetoothGATTCharacteristic {
             is_notifying: Arc::new(Mutex::new(is_notifying)),
             flags: Arc::new(Mutex::new(flags)),
             gatt_descriptors: Arc::new(Mutex::new(gatt_descriptors)),
+            notification_subscribers: Arc::new(Mutex::new(vec![])),
         });
         let _ = service.add_characteristic(characteristic.clone());
         characteristic
@@ -64,8 +74,6 @@ impl FakeBluetoothGATTCharacteristic {
 
     make_option_getter!(get_value, value, Vec<u8>);
 
-    make_setter!(set_value, value, Option<Vec<u8>>);
-
     make_getter!(is_notifying);
 
     make_setter!(set_notifying, is_notifying, bool);
@@ -74,63 +82,343 @@ impl FakeBluetoothGATTCharacteristic {
 
     make_setter!(set_flags, flags, Vec<String>);
 
-    make_getter!(get_gatt_descriptor_structs, gatt_descriptors, Vec<Arc<FakeBluetoothGATTDescriptor>>);
+    pub fn get_characteristic_flags(&self) -> Result<CharacteristicFlags, BlurmockError> {
+        CharacteristicFlags::from_strings(self.get_flags()?)
+    }
+
+    pub fn set_characteristic_flags(&self, flags: CharacteristicFlags) -> Result<(), BlurmockError> {
+        self.set_flags(flags.to_strings())
+    }
 
-    pub fn get_service(&self) -> Result<Arc<FakeBluetoothGATTService>, Box<Error>> {
+    pub fn get_service(&self) -> Result<Arc<FakeBluetoothGATTService>, BlurmockError> {
         Ok(self.service.clone())
     }
 
-    pub fn start_notify(&self) -> Result<(), Box<Error>> {
-        self.set_notifying(true)
+    pub fn check_connected(&self) -> Result<(), BlurmockError> {
+        self.service.check_connected()
+    }
+
+    pub(crate) fn begin_operation(&self, operation: Operation, object_id: &str) -> Result<(), BlurmockError> {
+        self.service.begin_operation(operation, object_id)
+    }
+
+    pub(crate) fn emit_event(&self, event: FakeBluetoothEvent) {
+        self.service.emit_event(event)
+    }
+
+    fn emit_property_changed(&self, property: &'static str, old: PropertyValue, new: PropertyValue) {
+        self.emit_event(FakeBluetoothEvent::PropertyChanged {
+            kind: ObjectKind::Characteristic,
+            object_id: self.get_id(),
+            property,
+            old,
+            new,
+        });
+    }
+
+    /// Sets the value and, while notifications are on, delivers it to every
+    /// receiver returned by `start_notify`.
+    pub fn set_value(&self, value: Option<Vec<u8>>) -> Result<(), BlurmockError> {
+        self.store_value(value.clone())?;
+        match value {
+            Some(value) => self.deliver_notification(value),
+            None => Ok(()),
+        }
+    }
+
+    /// Simulates the device pushing a new value to the notification subscribers.
+    pub fn notify_value(&self, value: Vec<u8>) -> Result<(), BlurmockError> {
+        self.set_value(Some(value))
+    }
+
+    /// Notifies `values` one after another, the first after `interval` of
+    /// clock time and each further one `interval` later.
+    pub fn schedule_notifications(&self, interval: Duration, values: Vec<Vec<u8>>) -> Result<Vec<TimerId>, BlurmockError> {
+        let clock = self.service.get_device()?.get_clock();
+        let mut timers = vec![];
+        let mut delay = Duration::from_secs(0);
+        for value in values {
+            delay += interval;
+            let characteristic = self.clone();
+            timers.push(clock.schedule(delay, move || {
+                let _ = characteristic.notify_value(value);
+            })?);
+        }
+        Ok(timers)
+    }
+
+    fn store_value(&self, value: Option<Vec<u8>>) -> Result<(), BlurmockError> {
+        let cloned = self.value.clone();
+        let mut value_to_change = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        let old_value = ::std::mem::replace(&mut *value_to_change, value.clone());
+        drop(value_to_change);
+        if old_value != value {
+            self.emit_property_changed("value", old_value.into(), value.into());
+        }
+        Ok(())
     }
 
-    pub fn stop_notify(&self) -> Result<(), Box<Error>> {
+    fn deliver_notification(&self, value: Vec<u8>) -> Result<(), BlurmockError> {
+        if !self.is_notifying()? {
+            return Ok(());
+        }
+        let cloned = self.notification_subscribers.clone();
+        let mut subscribers = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        subscribers.retain(|subscriber| subscriber.send(value.clone()).is_ok());
+        Ok(())
+    }
+
+    /// Turns notifications on and returns a new, independent receiver for the
+    /// values notified from now on. The receiver is disconnected by
+    /// `stop_notify` or when the device disconnects.
+    pub fn start_notify(&self) -> Result<Receiver<Vec<u8>>, BlurmockError> {
+        self.begin_operation(Operation::StartNotify, &self.get_id())?;
+        self.check_connected()?;
+        if !self.get_characteristic_flags()?.can_notify() {
+            return Err(BlurmockError::NotSupported);
+        }
+        let (sender, receiver) = mpsc::channel();
+        let cloned = self.notification_subscribers.clone();
+        let mut subscribers = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        subscribers.push(sender);
+        drop(subscribers);
+        self.set_notifying(true)?;
+        Ok(receiver)
+    }
+
+    pub fn stop_notify(&self) -> Result<(), BlurmockError> {
+        self.begin_operation(Operation::StopNotify, &self.get_id())?;
+        self.check_connected()?;
+        self.end_notifications()
+    }
+
+    // Disconnects the notification receivers, also used when the device
+    // disconnects.
+    pub(crate) fn end_notifications(&self) -> Result<(), BlurmockError> {
+        let cloned = self.notification_subscribers.clone();
+        let mut subscribers = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        subscribers.clear();
+        drop(subscribers);
         self.set_notifying(false)
     }
 
-    pub fn get_gatt_descriptors(&self) -> Result<Vec<String>, Box<Error>> {
+    pub fn get_gatt_descriptors(&self) -> Result<Vec<String>, BlurmockError> {
+        let gatt_descriptors = self.get_gatt_descriptor_structs()?;
+        Ok(gatt_descriptors.into_iter().map(|s| s.get_id()).collect())
+    }
+
+    pub fn get_gatt_descriptor_structs(&self) -> Result<Vec<Arc<FakeBluetoothGATTDescriptor>>, BlurmockError> {
+        self.begin_operation(Operation::GetGattDescriptors, &self.get_id())?;
+        self.check_connected()?;
+        self.get_gatt_descriptor_list()
+    }
+
+    /// Like `get_gatt_descriptor_structs`, but also works while the device is disconnected.
+    /// Used internally to maintain the GATT tree.
+    pub(crate) fn get_gatt_descriptor_list(&self) -> Result<Vec<Arc<FakeBluetoothGATTDescriptor>>, BlurmockError> {
         let cloned = self.gatt_descriptors.clone();
         let gatt_descriptors = match cloned.lock() {
             Ok(guard) => guard.deref().clone(),
-            Err(_) => return Err(Box::from("Could not get the value.")),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
         };
-        Ok(gatt_descriptors.into_iter().map(|s| s.get_id()).collect())
+        Ok(gatt_descriptors)
     }
 
-    pub fn get_gatt_descriptor(&self, id: String) -> Result<Arc<FakeBluetoothGATTDescriptor>, Box<Error>> {
-        let descriptors = try!(self.get_gatt_descriptor_structs());
-        for descriptor in descriptors {
-            let descriptor_id = descriptor.get_id();
-            if descriptor_id == id {
+    pub fn get_gatt_descriptor(&self, id: String) -> Result<Arc<FakeBluetoothGATTDescriptor>, BlurmockError> {
+        self.begin_operation(Operation::GetGattDescriptors, &self.get_id())?;
+        self.check_connected()?;
+        self.find_gatt_descriptor(id)
+    }
+
+    pub(crate) fn find_gatt_descriptor(&self, id: String) -> Result<Arc<FakeBluetoothGATTDescriptor>, BlurmockError> {
+        for descriptor in self.get_gatt_descriptor_list()? {
+            if descriptor.get_id() == id {
                 return Ok(descriptor);
             }
         }
-        Err(Box::from("No descriptor exists with the given id."))
+        Err(BlurmockError::NotFound { kind: ObjectKind::Descriptor, id: Some(id) })
     }
 
-    pub fn add_descriptor(&self, descriptor: Arc<FakeBluetoothGATTDescriptor>) -> Result<(), Box<Error>> {
+    pub fn add_descriptor(&self, descriptor: Arc<FakeBluetoothGATTDescriptor>) -> Result<(), BlurmockError> {
         let cloned = self.gatt_descriptors.clone();
         let mut gatt_descriptors = match cloned.lock() {
             Ok(guard) => guard,
-            Err(_) => return Err(Box::from("Could not get the value.")),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
         };
-        Ok(gatt_descriptors.push(descriptor))
+        let descriptor_id = descriptor.get_id();
+        gatt_descriptors.push(descriptor);
+        drop(gatt_descriptors);
+        self.emit_event(FakeBluetoothEvent::DescriptorAdded {
+            characteristic_id: self.get_id(),
+            descriptor_id,
+        });
+        Ok(())
     }
 
-    pub fn remove_descriptor(&self, id: String) -> Result<(), Box<Error>> {
+    pub fn remove_descriptor(&self, id: String) -> Result<(), BlurmockError> {
         let cloned = self.gatt_descriptors.clone();
         let mut gatt_descriptors = match cloned.lock() {
             Ok(guard) => guard,
-            Err(_) => return Err(Box::from("Could not get the value.")),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
         };
-        Ok(gatt_descriptors.retain(|d| d.get_id() != id))
+        let count = gatt_descriptors.len();
+        gatt_descriptors.retain(|d| d.get_id() != id);
+        let removed = gatt_descriptors.len() != count;
+        drop(gatt_descriptors);
+        if removed {
+            self.emit_event(FakeBluetoothEvent::DescriptorRemoved {
+                characteristic_id: self.get_id(),
+                descriptor_id: id,
+            });
+        }
+        Ok(())
+    }
+
+    fn is_paired(&self) -> Result<bool, BlurmockError> {
+        self.service.get_device()?.is_paired()
     }
 
-    pub fn read_value(&self) -> Result<Vec<u8>, Box<Error>> {
+    pub fn read_value(&self) -> Result<Vec<u8>, BlurmockError> {
+        self.begin_operation(Operation::ReadValue, &self.get_id())?;
+        self.check_connected()?;
+        let flags = self.get_characteristic_flags()?;
+        if !flags.can_read() {
+            return Err(BlurmockError::NotPermitted);
+        }
+        if flags.read_requires_pairing() && !self.is_paired()? {
+            return Err(BlurmockError::NotAuthorized);
+        }
         self.get_value()
     }
 
-    pub fn write_value(&self, value: Vec<u8>) -> Result<(), Box<Error>> {
-        self.set_value(Some(value))
+    pub fn write_value(&self, value: Vec<u8>) -> Result<(), BlurmockError> {
+        self.begin_operation(Operation::WriteValue, &self.get_id())?;
+        self.check_connected()?;
+        let flags = self.get_characteristic_flags()?;
+        if !flags.can_write() {
+            return Err(BlurmockError::NotPermitted);
+        }
+        if flags.write_requires_pairing() && !self.is_paired()? {
+            return Err(BlurmockError::NotAuthorized);
+        }
+        self.store_value(Some(value))
+    }
+
+    // A copy of the characteristic and its descriptors belonging to `service`,
+    // which is not told about it. Notification receivers are not copied, so
+    // the copy is not notifying.
+    pub(crate) fn deep_copy(&self, service: Arc<FakeBluetoothGATTService>)
+                            -> Result<Arc<FakeBluetoothGATTCharacteristic>, BlurmockError> {
+        let characteristic = Arc::new(FakeBluetoothGATTCharacteristic {
+            id: copy_value(&self.id)?,
+            uuid: copy_value(&self.uuid)?,
+            service,
+            value: copy_value(&self.value)?,
+            is_notifying: Arc::new(Mutex::new(false)),
+            flags: copy_value(&self.flags)?,
+            gatt_descriptors: Arc::new(Mutex::new(vec![])),
+            notification_subscribers: Arc::new(Mutex::new(vec![])),
+        });
+        let descriptors = self.get_gatt_descriptor_list()?
+                              .iter()
+                              .map(|descriptor| descriptor.deep_copy(characteristic.clone()))
+                              .collect::<Result<Vec<_>, _>>()?;
+        match characteristic.gatt_descriptors.lock() {
+            Ok(mut gatt_descriptors) => *gatt_descriptors = descriptors,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        }
+        Ok(characteristic)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FakeBluetoothGATTCharacteristicBuilder {
+    id: Option<String>,
+    uuid: String,
+    value: Option<Vec<u8>>,
+    is_notifying: bool,
+    flags: Vec<String>,
+    gatt_descriptors: Vec<FakeBluetoothGATTDescriptorBuilder>,
+}
+
+impl FakeBluetoothGATTCharacteristicBuilder {
+    pub fn new<S: Into<String>>(uuid: S) -> FakeBluetoothGATTCharacteristicBuilder {
+        FakeBluetoothGATTCharacteristicBuilder {
+            id: None,
+            uuid: uuid.into(),
+            value: None,
+            is_notifying: false,
+            flags: vec![],
+            gatt_descriptors: vec![],
+        }
+    }
+
+    /// Overrides the generated `<service id>/charXXXX` id.
+    pub fn id<S: Into<String>>(mut self, id: S) -> FakeBluetoothGATTCharacteristicBuilder {
+        self.id = Some(id.into());
+        self
+    }
+
+    pub fn value(mut self, value: Vec<u8>) -> FakeBluetoothGATTCharacteristicBuilder {
+        self.value = Some(value);
+        self
+    }
+
+    pub fn notifying(mut self, is_notifying: bool) -> FakeBluetoothGATTCharacteristicBuilder {
+        self.is_notifying = is_notifying;
+        self
+    }
+
+    pub fn flags<I, S>(mut self, flags: I) -> FakeBluetoothGATTCharacteristicBuilder
+        where I: IntoIterator<Item = S>,
+              S: Into<String>
+    {
+        self.flags = flags.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn descriptor<S, F>(mut self, uuid: S, build: F) -> FakeBluetoothGATTCharacteristicBuilder
+        where S: Into<String>,
+              F: FnOnce(FakeBluetoothGATTDescriptorBuilder) -> FakeBluetoothGATTDescriptorBuilder
+    {
+        self.gatt_descriptors.push(build(FakeBluetoothGATTDescriptorBuilder::new(uuid)));
+        self
+    }
+
+    /// Creates the characteristic with all of its descriptors and registers it
+    /// on `service`.
+    pub fn build(self, service: Arc<FakeBluetoothGATTService>) -> Arc<FakeBluetoothGATTCharacteristic> {
+        let id = match self.id {
+            Some(id) => id,
+            None => {
+                let ids: Vec<String> = service.get_gatt_characteristic_list()
+                                              .map(|characteristics| characteristics.iter().map(|c| c.get_id()).collect())
+                                              .unwrap_or_default();
+                free_child_id(&format!("{}/char", service.get_id()), &ids)
+            },
+        };
+        let characteristic = FakeBluetoothGATTCharacteristic::new(id,
+                                                                  self.uuid,
+                                                                  service,
+                                                                  self.value,
+                                                                  self.is_notifying,
+                                                                  self.flags,
+                                                                  vec!());
+        for descriptor in self.gatt_descriptors {
+            descriptor.build(characteristic.clone());
+        }
+        characteristic
     }
 }
@@ -1,6 +1,12 @@
 use core::ops::Deref;
+use error::{BlurmockError, ObjectKind};
+use event::{FakeBluetoothEvent, PropertyValue};
 use fake_characteristic::FakeBluetoothGATTCharacteristic;
-use std::error::Error;
+use fake_device::FakeBluetoothDevice;
+use flags::DescriptorFlags;
+use free_child_id;
+use operation::Operation;
+use snapshot::copy_value;
 use std::sync::{Arc, Mutex};
 
 #[derive(Clone, Debug)]
@@ -19,7 +25,7 @@ impl FakeBluetoothGATTDescriptor {
                value: Option<Vec<u8>>,
                flags: Vec<String>)
                -> Arc<FakeBluetoothGATTDescriptor> {
-        if let Ok(existing_descriptor) = characteristic.get_gatt_descriptor(id.clone()) {
+        if let Ok(existing_descriptor) = characteristic.find_gatt_descriptor(id.clone()) {
             return existing_descriptor;
         }
         let descriptor = Arc::new(FakeBluetoothGATTDescriptor {
@@ -61,15 +67,130 @@ impl FakeBluetoothGATTDescriptor {
 
     make_setter!(set_flags, flags, Vec<String>);
 
-    pub fn get_characteristic(&self) -> Result<Arc<FakeBluetoothGATTCharacteristic>, Box<Error>> {
+    pub fn get_descriptor_flags(&self) -> Result<DescriptorFlags, BlurmockError> {
+        DescriptorFlags::from_strings(self.get_flags()?)
+    }
+
+    pub fn set_descriptor_flags(&self, flags: DescriptorFlags) -> Result<(), BlurmockError> {
+        self.set_flags(flags.to_strings())
+    }
+
+    pub fn get_characteristic(&self) -> Result<Arc<FakeBluetoothGATTCharacteristic>, BlurmockError> {
         Ok(self.characteristic.clone())
     }
 
-    pub fn read_value(&self) -> Result<Vec<u8>, Box<Error>> {
+    pub(crate) fn emit_event(&self, event: FakeBluetoothEvent) {
+        self.characteristic.emit_event(event)
+    }
+
+    fn emit_property_changed(&self, property: &'static str, old: PropertyValue, new: PropertyValue) {
+        self.emit_event(FakeBluetoothEvent::PropertyChanged {
+            kind: ObjectKind::Descriptor,
+            object_id: self.get_id(),
+            property,
+            old,
+            new,
+        });
+    }
+
+    fn get_device(&self) -> Result<Arc<FakeBluetoothDevice>, BlurmockError> {
+        self.characteristic.get_service()?.get_device()
+    }
+
+    pub fn check_connected(&self) -> Result<(), BlurmockError> {
+        self.characteristic.check_connected()
+    }
+
+    pub fn read_value(&self) -> Result<Vec<u8>, BlurmockError> {
+        self.characteristic.begin_operation(Operation::ReadValue, &self.get_id())?;
+        self.check_connected()?;
+        let device = self.get_device()?;
+        let flags = self.get_descriptor_flags()?;
+        if !flags.can_read() {
+            return Err(BlurmockError::NotPermitted);
+        }
+        if flags.read_requires_pairing() && !device.is_paired()? {
+            return Err(BlurmockError::NotAuthorized);
+        }
         self.get_value()
     }
 
-    pub fn write_value(&self, value: Vec<u8>) -> Result<(), Box<Error>> {
+    pub fn write_value(&self, value: Vec<u8>) -> Result<(), BlurmockError> {
+        self.characteristic.begin_operation(Operation::WriteValue, &self.get_id())?;
+        self.check_connected()?;
+        let device = self.get_device()?;
+        let flags = self.get_descriptor_flags()?;
+        if !flags.can_write() {
+            return Err(BlurmockError::NotPermitted);
+        }
+        if flags.write_requires_pairing() && !device.is_paired()? {
+            return Err(BlurmockError::NotAuthorized);
+        }
         self.set_value(Some(value))
     }
+
+    // A copy of the descriptor belonging to `characteristic`, which is not told
+    // about it.
+    pub(crate) fn deep_copy(&self, characteristic: Arc<FakeBluetoothGATTCharacteristic>)
+                            -> Result<Arc<FakeBluetoothGATTDescriptor>, BlurmockError> {
+        Ok(Arc::new(FakeBluetoothGATTDescriptor {
+            id: copy_value(&self.id)?,
+            uuid: copy_value(&self.uuid)?,
+            characteristic,
+            value: copy_value(&self.value)?,
+            flags: copy_value(&self.flags)?,
+        }))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FakeBluetoothGATTDescriptorBuilder {
+    id: Option<String>,
+    uuid: String,
+    value: Option<Vec<u8>>,
+    flags: Vec<String>,
+}
+
+impl FakeBluetoothGATTDescriptorBuilder {
+    pub fn new<S: Into<String>>(uuid: S) -> FakeBluetoothGATTDescriptorBuilder {
+        FakeBluetoothGATTDescriptorBuilder {
+            id: None,
+            uuid: uuid.into(),
+            value: None,
+            flags: vec![],
+        }
+    }
+
+    /// Overrides the generated `<characteristic id>/descXXXX` id.
+    pub fn id<S: Into<String>>(mut self, id: S) -> FakeBluetoothGATTDescriptorBuilder {
+        self.id = Some(id.into());
+        self
+    }
+
+    pub fn value(mut self, value: Vec<u8>) -> FakeBluetoothGATTDescriptorBuilder {
+        self.value = Some(value);
+        self
+    }
+
+    pub fn flags<I, S>(mut self, flags: I) -> FakeBluetoothGATTDescriptorBuilder
+        where I: IntoIterator<Item = S>,
+              S: Into<String>
+    {
+        self.flags = flags.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Creates the descriptor and registers it on `characteristic`.
+    pub fn build(self, characteristic: Arc<FakeBluetoothGATTCharacteristic>) -> Arc<FakeBluetoothGATTDescriptor> {
+        let id = match self.id {
+            Some(id) => id,
+            None => {
+                let ids: Vec<String> = characteristic.get_gatt_descriptor_list()
+                                                     .map(|descriptors| descriptors.iter().map(|d| d.get_id()).collect())
+                                                     .unwrap_or_default();
+                free_child_id(&format!("{}/desc", characteristic.get_id()), &ids)
+            },
+        };
+        FakeBluetoothGATTDescriptor::new(id, self.uuid, characteristic, self.value, self.flags)
+    }
 }
@@ -1,10 +1,35 @@
+use advertising_data::{AdvertisementPacket, AdvertisingData};
+use clock::{FakeClock, TimerId};
 use core::ops::Deref;
+use error::{BlurmockError, ObjectKind};
+use event::{FakeBluetoothEvent, PropertyValue};
 use fake_adapter::FakeBluetoothAdapter;
-use fake_service::FakeBluetoothGATTService;
-use hex;
-use std::collections::HashMap;
-use std::error::Error;
+use fake_discovery_session::{normalize_uuid, DiscoverySchedule};
+use fake_service::{FakeBluetoothGATTService, FakeBluetoothGATTServiceBuilder};
+use latency::{Latency, LatencyRule};
+use modalias::{Modalias, ModaliasSource};
+use operation::Operation;
+use snapshot::copy_value;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub type ManufacturerData = HashMap<u16, Vec<u8>>;
+pub type ServiceData = HashMap<String, Vec<u8>>;
+
+/// What `connect_profile` does for a profile registered with `add_profile`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProfileOutcome {
+    Success,
+    Failure(BlurmockError),
+    NotAvailable,
+}
+
+#[derive(Clone, Debug)]
+struct FakeProfile {
+    outcome: ProfileOutcome,
+    is_connected: bool,
+}
 
 #[derive(Clone, Debug)]
 pub struct FakeBluetoothDevice {
@@ -28,11 +53,15 @@ pub struct FakeBluetoothDevice {
     rssi: Arc<Mutex<Option<i16>>>,
     tx_power: Arc<Mutex<Option<i16>>>,
     modalias: Arc<Mutex<String>>,
-    manufacturer_data: Arc<Mutex<Option<HashMap<u16, Vec<u8>>>>>,
-    service_data: Arc<Mutex<Option<HashMap<String, Vec<u8>>>>>,
+    manufacturer_data: Arc<Mutex<Option<ManufacturerData>>>,
+    service_data: Arc<Mutex<Option<ServiceData>>>,
+    profiles: Arc<Mutex<BTreeMap<String, FakeProfile>>>,
+    advertisement: Arc<Mutex<Option<AdvertisingData>>>,
+    scan_response: Arc<Mutex<Option<AdvertisingData>>>,
 }
 
 impl FakeBluetoothDevice {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(id: String,
                adapter: Arc<FakeBluetoothAdapter>,
                address: String,
@@ -53,38 +82,39 @@ impl FakeBluetoothDevice {
                rssi: Option<i16>,
                tx_power: Option<i16>,
                modalias: String,
-               manufacturer_data: Option<HashMap<u16, Vec<u8>>>,
-               service_data: Option<HashMap<String, Vec<u8>>>)
+               manufacturer_data: Option<ManufacturerData>,
+               service_data: Option<ServiceData>)
                -> Arc<FakeBluetoothDevice> {
-        if let Ok(existing_device) = adapter.get_device(id.clone()) {
-            return existing_device;
-        }
-        let device = Arc::new(FakeBluetoothDevice{
-            id: Arc::new(Mutex::new(id)),
-            adapter: adapter.clone(),
-            address: Arc::new(Mutex::new(address)),
-            appearance: Arc::new(Mutex::new(appearance)),
-            class: Arc::new(Mutex::new(class)),
-            gatt_services: Arc::new(Mutex::new(gatt_services)),
-            is_paired: Arc::new(Mutex::new(is_paired)),
-            is_connectable: Arc::new(Mutex::new(is_connectable)),
-            is_connected: Arc::new(Mutex::new(is_connected)),
-            is_trusted: Arc::new(Mutex::new(is_trusted)),
-            is_blocked: Arc::new(Mutex::new(is_blocked)),
-            is_legacy_pairing: Arc::new(Mutex::new(is_legacy_pairing)),
-            uuids: Arc::new(Mutex::new(uuids)),
-            name: Arc::new(Mutex::new(name)),
-            icon: Arc::new(Mutex::new(icon)),
-            alias: Arc::new(Mutex::new(alias)),
-            product_version: Arc::new(Mutex::new(product_version)),
-            rssi: Arc::new(Mutex::new(rssi)),
-            tx_power: Arc::new(Mutex::new(tx_power)),
-            modalias: Arc::new(Mutex::new(modalias)),
-            manufacturer_data: Arc::new(Mutex::new(manufacturer_data)),
-            service_data: Arc::new(Mutex::new(service_data)),
-        });
-        let _ = adapter.add_device(device.clone());
-        device
+        FakeBluetoothDeviceBuilder {
+            id,
+            adapter,
+            address,
+            appearance,
+            class,
+            gatt_services: vec![],
+            gatt_service_structs: gatt_services,
+            is_paired,
+            is_connectable,
+            is_connected,
+            is_trusted,
+            is_blocked,
+            is_legacy_pairing,
+            uuids,
+            name,
+            icon,
+            alias,
+            product_version,
+            rssi,
+            tx_power,
+            modalias,
+            manufacturer_data,
+            service_data,
+            profiles: vec![],
+            discovery_schedule: None,
+            connection_delay: Duration::from_secs(0),
+            advertisement: None,
+            scan_response: None,
+        }.build()
     }
 
     pub fn new_empty(adapter: Arc<FakeBluetoothAdapter>, device_id: String)
@@ -171,6 +201,10 @@ impl FakeBluetoothDevice {
 
     make_setter!(set_legacy_pairing, is_legacy_pairing, bool);
 
+    make_getter!(get_product_version, product_version, u32);
+
+    make_setter!(set_product_version, product_version, u32);
+
     make_setter!(set_modalias, modalias, String);
 
     make_option_getter!(get_rssi, rssi, i16);
@@ -181,147 +215,906 @@ impl FakeBluetoothDevice {
 
     make_setter!(set_tx_power, tx_power, Option<i16>);
 
-    make_option_getter!(get_manufacturer_data, manufacturer_data, HashMap<u16, Vec<u8>>);
+    make_option_getter!(get_manufacturer_data, manufacturer_data, ManufacturerData);
 
-    make_setter!(set_manufacturer_data, manufacturer_data, Option<HashMap<u16, Vec<u8>>>);
+    make_setter!(set_manufacturer_data, manufacturer_data, Option<ManufacturerData>);
 
-    make_option_getter!(get_service_data, service_data, HashMap<String, Vec<u8>>);
+    make_option_getter!(get_service_data, service_data, ServiceData);
 
-    make_setter!(set_service_data, service_data, Option<HashMap<String, Vec<u8>>>);
+    make_setter!(set_service_data, service_data, Option<ServiceData>);
 
-    pub fn get_adapter(&self) -> Result<Arc<FakeBluetoothAdapter>, Box<Error>> {
+    pub fn get_adapter(&self) -> Result<Arc<FakeBluetoothAdapter>, BlurmockError> {
         Ok(self.adapter.clone())
     }
 
-    pub fn pair(&self) -> Result<(), Box<Error>> {
+    pub(crate) fn emit_event(&self, event: FakeBluetoothEvent) {
+        self.adapter.emit_event(event)
+    }
+
+    fn emit_property_changed(&self, property: &'static str, old: PropertyValue, new: PropertyValue) {
+        self.emit_event(FakeBluetoothEvent::PropertyChanged {
+            kind: ObjectKind::Device,
+            object_id: self.get_id(),
+            property,
+            old,
+            new,
+        });
+    }
+
+    pub fn pair(&self) -> Result<(), BlurmockError> {
+        self.begin_operation(Operation::Pair, &self.get_id())?;
+        self.adapter.check_powered()?;
         self.set_paired(true)
     }
 
-    pub fn cancel_pairing(&self) -> Result<(), Box<Error>> {
+    pub fn cancel_pairing(&self) -> Result<(), BlurmockError> {
+        self.begin_operation(Operation::CancelPairing, &self.get_id())?;
+        self.adapter.check_powered()?;
         self.set_paired(false)
     }
 
-    pub fn get_modalias(&self) ->  Result<(String, u32, u32, u32), Box<Error>> {
+    pub(crate) fn begin_operation(&self, operation: Operation, object_id: &str) -> Result<(), BlurmockError> {
+        self.adapter.begin_operation(operation, object_id)
+    }
+
+    pub fn get_modalias(&self) -> Result<Modalias, BlurmockError> {
         let cloned = self.modalias.clone();
         let modalias = match cloned.lock() {
             Ok(guard) => guard.deref().clone(),
-            Err(_) => return Err(Box::from("Could not get the value.")),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
         };
+        Ok(modalias.parse::<Modalias>()?)
+    }
 
-        let ids: Vec<&str> = modalias.split(":").collect();
-        let source = String::from(ids[0]);
-        let vendor = hex::decode(&ids[1][1..5]).unwrap();
-        let product = hex::decode(&ids[1][6..10]).unwrap();
-        let device = hex::decode(&ids[1][11..15]).unwrap();
+    // The modalias as stored, which scenarios save without parsing it.
+    #[cfg(feature = "scenario")]
+    pub(crate) fn get_raw_modalias(&self) -> Result<String, BlurmockError> {
+        match self.modalias.lock() {
+            Ok(guard) => Ok(guard.deref().clone()),
+            Err(_) => Err(BlurmockError::LockPoisoned),
+        }
+    }
 
-        Ok((source,
-        (vendor[0] as u32) * 16 * 16 + (vendor[1] as u32),
-        (product[0] as u32) * 16 * 16 + (product[1] as u32),
-        (device[0] as u32) * 16 * 16 + (device[1] as u32)))
+    pub fn set_modalias_ids(&self,
+                            source: ModaliasSource,
+                            vendor: u32,
+                            product: u32,
+                            version: u32)
+                            -> Result<(), BlurmockError> {
+        self.set_modalias(Modalias::new(source, vendor, product, version).to_string())
     }
 
-    pub fn get_vendor_id_source(&self) -> Result<String, Box<Error>> {
-        let (vendor_id_source,_,_,_) = try!(self.get_modalias());
-        Ok(vendor_id_source)
+    pub fn get_vendor_id_source(&self) -> Result<String, BlurmockError> {
+        Ok(self.get_modalias()?.source.to_string())
     }
 
-    pub fn get_vendor_id(&self) -> Result<u32, Box<Error>> {
-        let (_,vendor_id,_,_) = try!(self.get_modalias());
-        Ok(vendor_id)
+    pub fn get_vendor_id(&self) -> Result<u32, BlurmockError> {
+        Ok(self.get_modalias()?.vendor)
     }
 
-    pub fn get_product_id(&self) -> Result<u32, Box<Error>> {
-        let (_,_,product_id,_) = try!(self.get_modalias());
-        Ok(product_id)
+    pub fn get_product_id(&self) -> Result<u32, BlurmockError> {
+        Ok(self.get_modalias()?.product)
     }
 
-    pub fn get_device_id(&self) -> Result<u32, Box<Error>> {
-        let (_,_,_,device_id) = try!(self.get_modalias());
-        Ok(device_id)
+    pub fn get_device_id(&self) -> Result<u32, BlurmockError> {
+        Ok(self.get_modalias()?.version)
     }
 
-    pub fn get_gatt_services(&self) -> Result<Vec<String>, Box<Error>> {
-        if !(try!(self.is_connected())) {
-            return Err(Box::from("Device not connected."));
+    /// Fails with `NotConnected` unless the device is connected, or with the
+    /// adapter's error if it is not present and powered. Every GATT
+    /// operation on the services, characteristics and descriptors below the
+    /// device calls this first, so handles obtained before a disconnect fail
+    /// until the device is connected again.
+    pub fn check_connected(&self) -> Result<(), BlurmockError> {
+        self.adapter.check_powered()?;
+        if !self.is_connected()? {
+            return Err(BlurmockError::NotConnected);
         }
+        Ok(())
+    }
 
-        let cloned = self.gatt_services.clone();
-        let gatt_services = match cloned.lock() {
-            Ok(guard) => guard.deref().clone(),
-            Err(_) => return Err(Box::from("Could not get the value.")),
-        };
+    pub fn get_gatt_services(&self) -> Result<Vec<String>, BlurmockError> {
+        let gatt_services = self.get_gatt_service_structs()?;
         Ok(gatt_services.into_iter().map(|s| s.get_id()).collect())
     }
 
-    pub fn get_gatt_service_structs(&self) -> Result<Vec<Arc<FakeBluetoothGATTService>>, Box<Error>> {
-        if !(try!(self.is_connected())) {
-            return Err(Box::from("Device not connected."));
-        }
+    pub fn get_gatt_service_structs(&self) -> Result<Vec<Arc<FakeBluetoothGATTService>>, BlurmockError> {
+        self.begin_operation(Operation::GetGattServices, &self.get_id())?;
+        self.check_connected()?;
+        self.get_gatt_service_list()
+    }
 
+    /// Like `get_gatt_service_structs`, but also works while the device is disconnected.
+    /// Used internally to maintain the GATT tree.
+    pub(crate) fn get_gatt_service_list(&self) -> Result<Vec<Arc<FakeBluetoothGATTService>>, BlurmockError> {
         let cloned = self.gatt_services.clone();
         let gatt_services = match cloned.lock() {
             Ok(guard) => guard.deref().clone(),
-            Err(_) => return Err(Box::from("Could not get the value.")),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
         };
         Ok(gatt_services)
     }
 
-    pub fn get_gatt_service(&self, id: String) -> Result<Arc<FakeBluetoothGATTService>, Box<Error>> {
-        let services = try!(self.get_gatt_service_structs());
-        for service in services {
-            let service_id = service.get_id();
-            if service_id == id {
+    pub fn get_gatt_service(&self, id: String) -> Result<Arc<FakeBluetoothGATTService>, BlurmockError> {
+        self.begin_operation(Operation::GetGattServices, &self.get_id())?;
+        self.check_connected()?;
+        self.find_gatt_service(id)
+    }
+
+    pub(crate) fn find_gatt_service(&self, id: String) -> Result<Arc<FakeBluetoothGATTService>, BlurmockError> {
+        for service in self.get_gatt_service_list()? {
+            if service.get_id() == id {
                 return Ok(service);
             }
         }
-        Err(Box::from("No service exists with the given id."))
+        Err(BlurmockError::NotFound { kind: ObjectKind::Service, id: Some(id) })
     }
 
-    pub fn add_service(&self, service: Arc<FakeBluetoothGATTService>) -> Result<(), Box<Error>> {
+    pub fn add_service(&self, service: Arc<FakeBluetoothGATTService>) -> Result<(), BlurmockError> {
         let cloned = self.gatt_services.clone();
         let mut gatt_services = match cloned.lock() {
             Ok(guard) => guard,
-            Err(_) => return Err(Box::from("Could not get the value.")),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
         };
-        Ok(gatt_services.push(service))
+        let service_id = service.get_id();
+        gatt_services.push(service);
+        drop(gatt_services);
+        self.emit_event(FakeBluetoothEvent::ServiceAdded {
+            device_id: self.get_id(),
+            service_id,
+        });
+        Ok(())
     }
 
-    pub fn remove_service(&self, id: String) -> Result<(), Box<Error>> {
+    pub fn remove_service(&self, id: String) -> Result<(), BlurmockError> {
         let cloned = self.gatt_services.clone();
         let mut gatt_services = match cloned.lock() {
             Ok(guard) => guard,
-            Err(_) => return Err(Box::from("Could not get the value.")),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        let count = gatt_services.len();
+        gatt_services.retain(|s| s.get_id() != id);
+        let removed = gatt_services.len() != count;
+        drop(gatt_services);
+        if removed {
+            self.emit_event(FakeBluetoothEvent::ServiceRemoved {
+                device_id: self.get_id(),
+                service_id: id,
+            });
+        }
+        Ok(())
+    }
+
+    /// Registers a profile the device supports and adds its uuid to `uuids`.
+    pub fn add_profile(&self, uuid: String, outcome: ProfileOutcome) -> Result<(), BlurmockError> {
+        let cloned = self.profiles.clone();
+        let mut profiles = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        profiles.insert(uuid.clone(), FakeProfile { outcome, is_connected: false });
+        drop(profiles);
+
+        let mut uuids = self.get_uuids()?;
+        if !uuids.contains(&uuid) {
+            uuids.push(uuid);
+            self.set_uuids(uuids)?;
+        }
+        Ok(())
+    }
+
+    /// Removes a profile and its uuid, disconnecting it without consulting
+    /// fault or latency rules.
+    pub fn remove_profile(&self, uuid: String) -> Result<(), BlurmockError> {
+        self.set_profile_connected(&uuid, false)?;
+        let cloned = self.profiles.clone();
+        let mut profiles = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        profiles.remove(&uuid);
+        drop(profiles);
+
+        let mut uuids = self.get_uuids()?;
+        uuids.retain(|u| *u != uuid);
+        self.set_uuids(uuids)
+    }
+
+    pub fn set_profile_outcome(&self, uuid: String, outcome: ProfileOutcome) -> Result<(), BlurmockError> {
+        let cloned = self.profiles.clone();
+        let mut profiles = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        match profiles.get_mut(&uuid) {
+            Some(profile) => profile.outcome = outcome,
+            None => return Err(BlurmockError::NotAvailable),
+        }
+        Ok(())
+    }
+
+    pub fn get_profiles(&self) -> Result<Vec<String>, BlurmockError> {
+        let cloned = self.profiles.clone();
+        let profiles = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        Ok(profiles.keys().cloned().collect())
+    }
+
+    pub fn get_connected_profiles(&self) -> Result<Vec<String>, BlurmockError> {
+        let cloned = self.profiles.clone();
+        let profiles = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        Ok(profiles.iter().filter(|&(_, p)| p.is_connected).map(|(uuid, _)| uuid.clone()).collect())
+    }
+
+    pub fn is_profile_connected(&self, uuid: String) -> Result<bool, BlurmockError> {
+        let cloned = self.profiles.clone();
+        let profiles = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        Ok(profiles.get(&uuid).is_some_and(|p| p.is_connected))
+    }
+
+    fn get_profile_outcome(&self, uuid: &str) -> Result<ProfileOutcome, BlurmockError> {
+        let cloned = self.profiles.clone();
+        let profiles = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        match profiles.get(uuid) {
+            Some(profile) => Ok(profile.outcome.clone()),
+            None => Err(BlurmockError::NotAvailable),
+        }
+    }
+
+    fn set_profile_connected(&self, uuid: &str, is_connected: bool) -> Result<(), BlurmockError> {
+        let old_value = self.get_connected_profiles()?;
+        let cloned = self.profiles.clone();
+        let mut profiles = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        if let Some(profile) = profiles.get_mut(uuid) {
+            profile.is_connected = is_connected;
+        }
+        drop(profiles);
+        let new_value = self.get_connected_profiles()?;
+        if old_value != new_value {
+            self.emit_property_changed("connected_profiles", old_value.into(), new_value.into());
+        }
+        Ok(())
+    }
+
+    /// Connects a single profile, bringing up the device connection first if
+    /// needed, like BlueZ's `Device1.ConnectProfile`. Bringing up the
+    /// connection takes the `Connect` latency, as in `connect`.
+    pub fn connect_profile(&self, uuid: String) -> Result<(), BlurmockError> {
+        self.begin_operation(Operation::ConnectProfile, &self.get_id())?;
+        match self.get_profile_outcome(&uuid)? {
+            ProfileOutcome::Success => (),
+            ProfileOutcome::Failure(err) => return Err(err),
+            ProfileOutcome::NotAvailable => return Err(BlurmockError::NotAvailable),
+        }
+        self.adapter.check_powered()?;
+        if !self.is_connected()? {
+            if !self.is_connectable()? {
+                return Err(BlurmockError::ConnectionFailed);
+            }
+            let delay = self.adapter.get_latency_injector().delay(Operation::Connect, &self.get_id())?;
+            self.get_clock().sleep(delay)?;
+            self.set_connected(true)?;
+        }
+        self.set_profile_connected(&uuid, true)
+    }
+
+    pub fn disconnect_profile(&self, uuid: String) -> Result<(), BlurmockError> {
+        self.begin_operation(Operation::DisconnectProfile, &self.get_id())?;
+        self.get_profile_outcome(&uuid)?;
+        if !self.is_profile_connected(uuid.clone())? {
+            return Err(BlurmockError::NotConnected);
+        }
+        self.set_profile_connected(&uuid, false)
+    }
+
+    make_option_getter!(get_advertisement, advertisement, AdvertisingData);
+
+    /// Sets the payload the device advertises. While the adapter is
+    /// discovering, its fields show up on the device right away.
+    pub fn set_advertisement(&self, advertisement: Option<AdvertisingData>) -> Result<(), BlurmockError> {
+        self.replace_advertising_data(&self.advertisement, "advertisement", advertisement)
+    }
+
+    make_option_getter!(get_scan_response, scan_response, AdvertisingData);
+
+    /// Sets the payload the device answers scan requests with. Its fields only
+    /// show up on the device while an active scan is running.
+    pub fn set_scan_response(&self, scan_response: Option<AdvertisingData>) -> Result<(), BlurmockError> {
+        self.replace_advertising_data(&self.scan_response, "scan_response", scan_response)
+    }
+
+    fn replace_advertising_data(&self,
+                                payload: &Arc<Mutex<Option<AdvertisingData>>>,
+                                property: &'static str,
+                                value: Option<AdvertisingData>)
+                                -> Result<(), BlurmockError> {
+        let mut value_to_change = match payload.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
         };
-        Ok(gatt_services.retain(|s| s.get_id() != id))
+        let old_value = ::std::mem::replace(&mut *value_to_change, value.clone());
+        drop(value_to_change);
+        if old_value != value {
+            self.emit_property_changed(property, old_value.into(), value.into());
+        }
+        if self.adapter.is_discovering()? && self.adapter.get_device(self.get_id()).is_ok() {
+            self.receive_advertising_data(self.adapter.is_active_scanning()?)?;
+        }
+        Ok(())
+    }
+
+    /// Updates the device properties from the advertisement and, for an active
+    /// scan, the scan response, which wins where both carry a field. Fields
+    /// neither payload carries keep their value, as they do in BlueZ.
+    pub(crate) fn receive_advertising_data(&self, active_scanning: bool) -> Result<(), BlurmockError> {
+        let mut payloads = vec![];
+        payloads.extend(self.get_advertisement().ok());
+        if active_scanning {
+            payloads.extend(self.get_scan_response().ok());
+        }
+
+        for payload in payloads {
+            self.receive_advertisement(&AdvertisementPacket::from(&payload))?;
+        }
+        Ok(())
+    }
+
+    /// Updates the properties the packet carries. Manufacturer and service
+    /// data entries replace those with the same key, uuids are added.
+    pub(crate) fn receive_advertisement(&self, packet: &AdvertisementPacket) -> Result<(), BlurmockError> {
+        if let Some(rssi) = packet.rssi {
+            self.set_rssi(Some(rssi))?;
+        }
+        if let Some(ref name) = packet.name {
+            self.set_name(Some(name.clone()))?;
+        }
+        if let Some(tx_power) = packet.tx_power {
+            self.set_tx_power(Some(tx_power))?;
+        }
+        if let Some(appearance) = packet.appearance {
+            self.set_appearance(Some(appearance))?;
+        }
+        if !packet.uuids.is_empty() {
+            let mut uuids = self.get_uuids()?;
+            for uuid in packet.uuids.iter().map(|u| normalize_uuid(u)) {
+                if !uuids.contains(&uuid) {
+                    uuids.push(uuid);
+                }
+            }
+            self.set_uuids(uuids)?;
+        }
+        if !packet.manufacturer_data.is_empty() {
+            let mut manufacturer_data = self.get_manufacturer_data().unwrap_or_default();
+            manufacturer_data.extend(packet.manufacturer_data.clone());
+            self.set_manufacturer_data(Some(manufacturer_data))?;
+        }
+        if !packet.service_data.is_empty() {
+            let mut service_data = self.get_service_data().unwrap_or_default();
+            service_data.extend(packet.service_data.clone());
+            self.set_service_data(Some(service_data))?;
+        }
+        Ok(())
+    }
+
+    /// The fixed `Connect` latency of this device, zero without one. Fails
+    /// with `NoValue` if the device's `Connect` latency is random.
+    pub fn get_connection_delay(&self) -> Result<Duration, BlurmockError> {
+        let id = self.get_id();
+        let rule = self.adapter.get_latency_injector()
+                               .get_rules()?
+                               .into_iter()
+                               .find(|r| r.operation == Operation::Connect && r.target.as_ref() == Some(&id));
+        match rule.map(|r| r.latency) {
+            None => Ok(Duration::from_secs(0)),
+            Some(Latency::Fixed(delay)) => Ok(delay),
+            Some(_) => Err(BlurmockError::NoValue),
+        }
+    }
+
+    /// How long `connect` takes, in clock time. This is the `Connect` latency
+    /// rule for this device, which it replaces: a rule for every device only
+    /// applies to devices without a connection delay.
+    pub fn set_connection_delay(&self, connection_delay: Duration) -> Result<(), BlurmockError> {
+        if connection_delay == Duration::from_secs(0) {
+            self.adapter.remove_latency(Operation::Connect, Some(self.get_id()))?;
+            return Ok(());
+        }
+        self.adapter.set_latency(LatencyRule::new(Operation::Connect, Latency::Fixed(connection_delay)).on(self.get_id()))
     }
 
-    pub fn connect_profile(&self, _uuid: String) -> Result<(), Box<Error>> {
-        unimplemented!();
+    pub fn get_clock(&self) -> FakeClock {
+        self.adapter.get_clock()
     }
 
-    pub fn disconnect_profile(&self, _uuid: String) -> Result<(), Box<Error>> {
-        unimplemented!();
+    /// Changes the RSSI after `delay` of clock time, e.g. to simulate the
+    /// device moving away.
+    pub fn schedule_rssi(&self, delay: Duration, rssi: Option<i16>) -> Result<TimerId, BlurmockError> {
+        let device = self.clone();
+        self.get_clock().schedule(delay, move || {
+            let _ = device.set_rssi(rssi);
+        })
     }
 
-    pub fn connect(&self) -> Result<(), Box<Error>> {
-        let is_connectable = try!(self.is_connectable());
-        let is_connected = try!(self.is_connected());
+    /// Connects the device and every profile whose outcome is `Success`. As
+    /// with BlueZ's `Device1.Connect`, a device with registered profiles only
+    /// connects if at least one of them does.
+    pub fn connect(&self) -> Result<(), BlurmockError> {
+        self.begin_operation(Operation::Connect, &self.get_id())?;
+        self.adapter.check_powered()?;
+        let is_connectable = self.is_connectable()?;
+        let is_connected = self.is_connected()?;
 
         if is_connected {
             return Ok(());
         }
-        if is_connectable {
-            return self.set_connected(true);
+        if !is_connectable {
+            return Err(BlurmockError::ConnectionFailed);
+        }
+
+        let mut first_error = None;
+        let mut connected_profiles = vec![];
+        for uuid in self.get_profiles()? {
+            match self.get_profile_outcome(&uuid)? {
+                ProfileOutcome::Success => connected_profiles.push(uuid),
+                ProfileOutcome::Failure(err) => first_error = first_error.or(Some(err)),
+                ProfileOutcome::NotAvailable => first_error = first_error.or(Some(BlurmockError::NotAvailable)),
+            }
+        }
+        if connected_profiles.is_empty() {
+            if let Some(err) = first_error {
+                return Err(err);
+            }
+        }
+
+        self.set_connected(true)?;
+        for uuid in connected_profiles {
+            self.set_profile_connected(&uuid, true)?;
         }
-        return Err(Box::from("Could not connect to the device."));
+        Ok(())
     }
 
-    pub fn disconnect(&self) -> Result<(), Box<Error>>{
-        let is_connected = try!(self.is_connected());
+    pub fn disconnect(&self) -> Result<(), BlurmockError>{
+        self.begin_operation(Operation::Disconnect, &self.get_id())?;
+        let is_connected = self.is_connected()?;
 
         if is_connected {
-            return self.set_connected(false);
+            return self.drop_connection();
+        }
+        Err(BlurmockError::NotConnected)
+    }
+
+    // Tears the connection down without consulting fault rules, for
+    // disconnects the code under test did not ask for.
+    pub(crate) fn drop_connection(&self) -> Result<(), BlurmockError> {
+        for uuid in self.get_connected_profiles()? {
+            self.set_profile_connected(&uuid, false)?;
+        }
+        self.stop_notifications()?;
+        self.set_connected(false)
+    }
+
+    fn stop_notifications(&self) -> Result<(), BlurmockError> {
+        for service in self.get_gatt_service_list()? {
+            for characteristic in service.get_gatt_characteristic_list()? {
+                if characteristic.is_notifying()? {
+                    characteristic.end_notifications()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // A copy of the device and its GATT tree belonging to `adapter`, which is
+    // not told about it.
+    pub(crate) fn deep_copy(&self, adapter: Arc<FakeBluetoothAdapter>) -> Result<Arc<FakeBluetoothDevice>, BlurmockError> {
+        let device = Arc::new(FakeBluetoothDevice {
+            id: copy_value(&self.id)?,
+            adapter,
+            address: copy_value(&self.address)?,
+            appearance: copy_value(&self.appearance)?,
+            class: copy_value(&self.class)?,
+            gatt_services: Arc::new(Mutex::new(vec![])),
+            is_paired: copy_value(&self.is_paired)?,
+            is_connectable: copy_value(&self.is_connectable)?,
+            is_connected: copy_value(&self.is_connected)?,
+            is_trusted: copy_value(&self.is_trusted)?,
+            is_blocked: copy_value(&self.is_blocked)?,
+            is_legacy_pairing: copy_value(&self.is_legacy_pairing)?,
+            uuids: copy_value(&self.uuids)?,
+            name: copy_value(&self.name)?,
+            icon: copy_value(&self.icon)?,
+            alias: copy_value(&self.alias)?,
+            product_version: copy_value(&self.product_version)?,
+            rssi: copy_value(&self.rssi)?,
+            tx_power: copy_value(&self.tx_power)?,
+            modalias: copy_value(&self.modalias)?,
+            manufacturer_data: copy_value(&self.manufacturer_data)?,
+            service_data: copy_value(&self.service_data)?,
+            profiles: copy_value(&self.profiles)?,
+            advertisement: copy_value(&self.advertisement)?,
+            scan_response: copy_value(&self.scan_response)?,
+        });
+        let services = self.get_gatt_service_list()?
+                           .iter()
+                           .map(|service| service.deep_copy(device.clone()))
+                           .collect::<Result<Vec<_>, _>>()?;
+        for service in &services {
+            service.retarget_included_services(&services)?;
+        }
+        match device.gatt_services.lock() {
+            Ok(mut gatt_services) => *gatt_services = services,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        }
+        Ok(device)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FakeBluetoothDeviceBuilder {
+    id: String,
+    adapter: Arc<FakeBluetoothAdapter>,
+    address: String,
+    appearance: Option<u16>,
+    class: u32,
+    gatt_services: Vec<FakeBluetoothGATTServiceBuilder>,
+    gatt_service_structs: Vec<Arc<FakeBluetoothGATTService>>,
+    is_paired: bool,
+    is_connectable: bool,
+    is_connected: bool,
+    is_trusted: bool,
+    is_blocked: bool,
+    is_legacy_pairing: bool,
+    uuids: Vec<String>,
+    name: Option<String>,
+    icon: String,
+    alias: String,
+    product_version: u32,
+    rssi: Option<i16>,
+    tx_power: Option<i16>,
+    modalias: String,
+    manufacturer_data: Option<ManufacturerData>,
+    service_data: Option<ServiceData>,
+    profiles: Vec<(String, ProfileOutcome)>,
+    discovery_schedule: Option<DiscoverySchedule>,
+    connection_delay: Duration,
+    advertisement: Option<AdvertisingData>,
+    scan_response: Option<AdvertisingData>,
+}
+
+impl FakeBluetoothDeviceBuilder {
+    pub fn new<S: Into<String>>(adapter: Arc<FakeBluetoothAdapter>, id: S) -> FakeBluetoothDeviceBuilder {
+        FakeBluetoothDeviceBuilder {
+            id: id.into(),
+            adapter,
+            address: String::new(),
+            appearance: None,
+            class: 0,
+            gatt_services: vec![],
+            gatt_service_structs: vec![],
+            is_paired: false,
+            is_connectable: false,
+            is_connected: false,
+            is_trusted: false,
+            is_blocked: false,
+            is_legacy_pairing: false,
+            uuids: vec![],
+            name: None,
+            icon: String::new(),
+            alias: String::new(),
+            product_version: 0,
+            rssi: None,
+            tx_power: None,
+            modalias: String::new(),
+            manufacturer_data: None,
+            service_data: None,
+            profiles: vec![],
+            discovery_schedule: None,
+            connection_delay: Duration::from_secs(0),
+            advertisement: None,
+            scan_response: None,
+        }
+    }
+
+    pub fn address<S: Into<String>>(mut self, address: S) -> FakeBluetoothDeviceBuilder {
+        self.address = address.into();
+        self
+    }
+
+    pub fn appearance(mut self, appearance: u16) -> FakeBluetoothDeviceBuilder {
+        self.appearance = Some(appearance);
+        self
+    }
+
+    pub fn class(mut self, class: u32) -> FakeBluetoothDeviceBuilder {
+        self.class = class;
+        self
+    }
+
+    pub fn paired(mut self, is_paired: bool) -> FakeBluetoothDeviceBuilder {
+        self.is_paired = is_paired;
+        self
+    }
+
+    pub fn connectable(mut self, is_connectable: bool) -> FakeBluetoothDeviceBuilder {
+        self.is_connectable = is_connectable;
+        self
+    }
+
+    pub fn connected(mut self, is_connected: bool) -> FakeBluetoothDeviceBuilder {
+        self.is_connected = is_connected;
+        self
+    }
+
+    pub fn trusted(mut self, is_trusted: bool) -> FakeBluetoothDeviceBuilder {
+        self.is_trusted = is_trusted;
+        self
+    }
+
+    pub fn blocked(mut self, is_blocked: bool) -> FakeBluetoothDeviceBuilder {
+        self.is_blocked = is_blocked;
+        self
+    }
+
+    pub fn legacy_pairing(mut self, is_legacy_pairing: bool) -> FakeBluetoothDeviceBuilder {
+        self.is_legacy_pairing = is_legacy_pairing;
+        self
+    }
+
+    pub fn uuids(mut self, uuids: Vec<String>) -> FakeBluetoothDeviceBuilder {
+        self.uuids = uuids;
+        self
+    }
+
+    pub fn uuid<S: Into<String>>(mut self, uuid: S) -> FakeBluetoothDeviceBuilder {
+        self.uuids.push(uuid.into());
+        self
+    }
+
+    pub fn name<S: Into<String>>(mut self, name: S) -> FakeBluetoothDeviceBuilder {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn icon<S: Into<String>>(mut self, icon: S) -> FakeBluetoothDeviceBuilder {
+        self.icon = icon.into();
+        self
+    }
+
+    pub fn alias<S: Into<String>>(mut self, alias: S) -> FakeBluetoothDeviceBuilder {
+        self.alias = alias.into();
+        self
+    }
+
+    pub fn product_version(mut self, product_version: u32) -> FakeBluetoothDeviceBuilder {
+        self.product_version = product_version;
+        self
+    }
+
+    pub fn rssi(mut self, rssi: i16) -> FakeBluetoothDeviceBuilder {
+        self.rssi = Some(rssi);
+        self
+    }
+
+    pub fn tx_power(mut self, tx_power: i16) -> FakeBluetoothDeviceBuilder {
+        self.tx_power = Some(tx_power);
+        self
+    }
+
+    pub fn modalias<S: Into<String>>(mut self, modalias: S) -> FakeBluetoothDeviceBuilder {
+        self.modalias = modalias.into();
+        self
+    }
+
+    pub fn manufacturer_data(mut self, manufacturer_data: ManufacturerData) -> FakeBluetoothDeviceBuilder {
+        self.manufacturer_data = Some(manufacturer_data);
+        self
+    }
+
+    pub fn service_data(mut self, service_data: ServiceData) -> FakeBluetoothDeviceBuilder {
+        self.service_data = Some(service_data);
+        self
+    }
+
+    pub fn profile<S: Into<String>>(mut self, uuid: S, outcome: ProfileOutcome) -> FakeBluetoothDeviceBuilder {
+        self.profiles.push((uuid.into(), outcome));
+        self
+    }
+
+    /// Keeps the device hidden until a discovery session finds it, see
+    /// `FakeBluetoothAdapter::add_undiscovered_device`.
+    pub fn discovered(mut self, schedule: DiscoverySchedule) -> FakeBluetoothDeviceBuilder {
+        self.discovery_schedule = Some(schedule);
+        self
+    }
+
+    pub fn connection_delay(mut self, connection_delay: Duration) -> FakeBluetoothDeviceBuilder {
+        self.connection_delay = connection_delay;
+        self
+    }
+
+    pub fn advertisement(mut self, advertisement: AdvertisingData) -> FakeBluetoothDeviceBuilder {
+        self.advertisement = Some(advertisement);
+        self
+    }
+
+    pub fn scan_response(mut self, scan_response: AdvertisingData) -> FakeBluetoothDeviceBuilder {
+        self.scan_response = Some(scan_response);
+        self
+    }
+
+    pub fn service<S, F>(mut self, uuid: S, build: F) -> FakeBluetoothDeviceBuilder
+        where S: Into<String>,
+              F: FnOnce(FakeBluetoothGATTServiceBuilder) -> FakeBluetoothGATTServiceBuilder
+    {
+        self.gatt_services.push(build(FakeBluetoothGATTServiceBuilder::new(uuid)));
+        self
+    }
+
+    /// Creates the device, registers it on the adapter and builds the whole
+    /// GATT tree below it. If the adapter already has a device with the same
+    /// id, that device is returned and the builder's properties are ignored,
+    /// just like `FakeBluetoothDevice::new` does.
+    pub fn build(self) -> Arc<FakeBluetoothDevice> {
+        let adapter = self.adapter.clone();
+        if let Ok(existing_device) = adapter.get_device(self.id.clone()) {
+            return existing_device;
+        }
+        if let Some(existing_device) = adapter.find_undiscovered_device(&self.id) {
+            return existing_device;
+        }
+        let device = Arc::new(FakeBluetoothDevice {
+            id: Arc::new(Mutex::new(self.id)),
+            adapter: self.adapter,
+            address: Arc::new(Mutex::new(self.address)),
+            appearance: Arc::new(Mutex::new(self.appearance)),
+            class: Arc::new(Mutex::new(self.class)),
+            gatt_services: Arc::new(Mutex::new(self.gatt_service_structs)),
+            is_paired: Arc::new(Mutex::new(self.is_paired)),
+            is_connectable: Arc::new(Mutex::new(self.is_connectable)),
+            is_connected: Arc::new(Mutex::new(self.is_connected)),
+            is_trusted: Arc::new(Mutex::new(self.is_trusted)),
+            is_blocked: Arc::new(Mutex::new(self.is_blocked)),
+            is_legacy_pairing: Arc::new(Mutex::new(self.is_legacy_pairing)),
+            uuids: Arc::new(Mutex::new(self.uuids)),
+            name: Arc::new(Mutex::new(self.name)),
+            icon: Arc::new(Mutex::new(self.icon)),
+            alias: Arc::new(Mutex::new(self.alias)),
+            product_version: Arc::new(Mutex::new(self.product_version)),
+            rssi: Arc::new(Mutex::new(self.rssi)),
+            tx_power: Arc::new(Mutex::new(self.tx_power)),
+            modalias: Arc::new(Mutex::new(self.modalias)),
+            manufacturer_data: Arc::new(Mutex::new(self.manufacturer_data)),
+            service_data: Arc::new(Mutex::new(self.service_data)),
+            profiles: Arc::new(Mutex::new(BTreeMap::new())),
+            advertisement: Arc::new(Mutex::new(self.advertisement)),
+            scan_response: Arc::new(Mutex::new(self.scan_response)),
+        });
+        let _ = match self.discovery_schedule {
+            Some(schedule) => adapter.add_undiscovered_device(device.clone(), schedule),
+            None => adapter.add_device(device.clone()),
+        };
+        for (uuid, outcome) in self.profiles {
+            let _ = device.add_profile(uuid, outcome);
+        }
+        if self.connection_delay > Duration::from_secs(0) {
+            let _ = device.set_connection_delay(self.connection_delay);
+        }
+        for service in self.gatt_services {
+            service.build(device.clone());
+        }
+        device
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clock::FakeClock;
+    use error::BlurmockError;
+    use fake_adapter::FakeBluetoothAdapter;
+    use fault::FaultRule;
+    use latency::{Latency, LatencyRule};
+    use operation::Operation;
+    use std::thread;
+    use std::time::Duration;
+    use super::ProfileOutcome;
+
+    const HFP_UUID: &str = "0000111e-0000-1000-8000-00805f9b34fb";
+
+    // Waits until a thread sleeps on the manual clock, so advancing is sure to
+    // wake it.
+    fn wait_for_sleeper(clock: &FakeClock) {
+        while clock.sleeping_thread_count().unwrap() == 0 {
+            thread::yield_now();
         }
-        return Err(Box::from("The device is not connected."));
+    }
+
+    #[test]
+    fn connect_profile_waits_for_the_connection_delay() {
+        let clock = FakeClock::manual();
+        let adapter = FakeBluetoothAdapter::builder().powered(true).clock(clock.clone()).build();
+        let device = adapter.device("device")
+                            .connectable(true)
+                            .profile(HFP_UUID, ProfileOutcome::Success)
+                            .connection_delay(Duration::from_secs(5))
+                            .build();
+
+        let connecting = device.clone();
+        let connection = thread::spawn(move || connecting.connect_profile(String::from(HFP_UUID)));
+        wait_for_sleeper(&clock);
+        assert_eq!(device.is_connected(), Ok(false));
+        clock.advance(Duration::from_secs(5)).unwrap();
+        assert_eq!(connection.join().unwrap(), Ok(()));
+        assert_eq!(device.is_profile_connected(String::from(HFP_UUID)), Ok(true));
+    }
+
+    #[test]
+    fn remove_profile_ignores_faults() {
+        let adapter = FakeBluetoothAdapter::builder().powered(true).build();
+        let device = adapter.device("device")
+                            .connectable(true)
+                            .profile(HFP_UUID, ProfileOutcome::Success)
+                            .build();
+        device.connect_profile(String::from(HFP_UUID)).unwrap();
+        adapter.inject_fault(FaultRule::new(Operation::DisconnectProfile, BlurmockError::NotPermitted)).unwrap();
+
+        assert_eq!(device.remove_profile(String::from(HFP_UUID)), Ok(()));
+        assert_eq!(device.get_profiles(), Ok(vec![]));
+        assert_eq!(device.get_connected_profiles(), Ok(vec![]));
+        assert_eq!(device.get_uuids(), Ok(vec![]));
+    }
+
+    #[test]
+    fn connection_delay_is_the_connect_latency_of_the_device() {
+        let clock = FakeClock::manual();
+        let adapter = FakeBluetoothAdapter::builder().powered(true).clock(clock.clone()).build();
+        let device = adapter.device("device")
+                            .connectable(true)
+                            .connection_delay(Duration::from_secs(5))
+                            .build();
+        adapter.set_latency(LatencyRule::new(Operation::Connect, Latency::Fixed(Duration::from_secs(60)))).unwrap();
+        assert_eq!(device.get_connection_delay(), Ok(Duration::from_secs(5)));
+        assert_eq!(adapter.get_latency_injector().get_rules().unwrap().len(), 2);
+
+        let connecting = device.clone();
+        let connection = thread::spawn(move || connecting.connect());
+        wait_for_sleeper(&clock);
+        clock.advance(Duration::from_secs(4)).unwrap();
+        assert_eq!(clock.sleeping_thread_count(), Ok(1));
+        assert_eq!(device.is_connected(), Ok(false));
+        clock.advance(Duration::from_secs(1)).unwrap();
+        assert_eq!(connection.join().unwrap(), Ok(()));
+        assert_eq!(device.is_connected(), Ok(true));
+        assert_eq!(clock.sleeping_thread_count(), Ok(0));
+
+        device.set_connection_delay(Duration::from_secs(0)).unwrap();
+        assert_eq!(device.get_connection_delay(), Ok(Duration::from_secs(0)));
+        assert_eq!(adapter.get_latency_injector().get_rules().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn pairing_needs_a_powered_adapter() {
+        let adapter = FakeBluetoothAdapter::builder().build();
+        let device = adapter.device("device").build();
+        assert_eq!(device.pair(), Err(BlurmockError::AdapterNotPowered));
+        assert_eq!(device.cancel_pairing(), Err(BlurmockError::AdapterNotPowered));
+        assert_eq!(device.is_paired(), Ok(false));
+
+        adapter.set_powered(true).unwrap();
+        assert_eq!(device.pair(), Ok(()));
+        assert_eq!(device.is_paired(), Ok(true));
     }
 }
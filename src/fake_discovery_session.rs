@@ -1,20 +1,162 @@
+use error::BlurmockError;
 use fake_adapter::FakeBluetoothAdapter;
-use std::error::Error;
+use fake_device::FakeBluetoothDevice;
+use operation::Operation;
 use std::sync::Arc;
+use std::time::Duration;
+
+/// When a device registered with `FakeBluetoothAdapter::add_undiscovered_device`
+/// is found and added to the adapter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiscoverySchedule {
+    /// Found once discovery has been running for the given time. The time is
+    /// counted from the latest start of discovery.
+    AfterDiscovering(Duration),
+    /// Found by a call to `FakeBluetoothAdapter::advance_discovery`.
+    OnAdvance,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub enum DiscoveryTransport {
+    #[default]
+    Auto,
+    BrEdr,
+    Le,
+}
+
+/// The parameters of BlueZ's `Adapter1.SetDiscoveryFilter`. Fake devices do
+/// not know which transport they were found on, so `transport` is merged
+/// and reported but never used to hide devices.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiscoveryFilter {
+    pub uuids: Vec<String>,
+    pub rssi: Option<i16>,
+    pub pathloss: Option<u16>,
+    pub transport: DiscoveryTransport,
+    pub duplicate_data: bool,
+    pub pattern: Option<String>,
+}
+
+impl Default for DiscoveryFilter {
+    fn default() -> DiscoveryFilter {
+        DiscoveryFilter {
+            uuids: vec![],
+            rssi: None,
+            pathloss: None,
+            transport: DiscoveryTransport::Auto,
+            duplicate_data: true,
+            pattern: None,
+        }
+    }
+}
+
+// Expands 16 and 32 bit uuids to 128 bit ones so that "180d" matches
+// "0000180d-0000-1000-8000-00805f9b34fb".
+pub(crate) fn normalize_uuid(uuid: &str) -> String {
+    let uuid = uuid.to_lowercase();
+    match uuid.len() {
+        4 => format!("0000{}-0000-1000-8000-00805f9b34fb", uuid),
+        8 => format!("{}-0000-1000-8000-00805f9b34fb", uuid),
+        _ => uuid,
+    }
+}
+
+impl DiscoveryFilter {
+    /// Merges the filters of several sessions, `None` standing for a session
+    /// without a filter. As in BlueZ, the result lets through every device
+    /// that any of the sessions would see, so a session without a filter gives
+    /// an empty filter. Otherwise uuid lists are joined (an empty list means
+    /// all devices), the weakest RSSI and pathloss thresholds win,
+    /// different transports give `Auto` and duplicates are reported if anyone
+    /// asked for them. A pattern is kept only when every session uses it.
+    pub fn merge(filters: &[Option<&DiscoveryFilter>]) -> DiscoveryFilter {
+        let mut merged = DiscoveryFilter::default();
+        let filters: Vec<&DiscoveryFilter> = match filters.iter().cloned().collect() {
+            Some(filters) => filters,
+            None => return merged,
+        };
+        if filters.is_empty() {
+            return merged;
+        }
+
+        if filters.iter().all(|f| !f.uuids.is_empty()) {
+            for filter in &filters {
+                for uuid in &filter.uuids {
+                    if !merged.uuids.contains(uuid) {
+                        merged.uuids.push(uuid.clone());
+                    }
+                }
+            }
+        }
+        merged.rssi = filters.iter().map(|f| f.rssi).collect::<Option<Vec<i16>>>()
+                             .and_then(|values| values.into_iter().min());
+        merged.pathloss = filters.iter().map(|f| f.pathloss).collect::<Option<Vec<u16>>>()
+                                 .and_then(|values| values.into_iter().max());
+        merged.transport = filters[0].transport;
+        if filters.iter().any(|f| f.transport != merged.transport) {
+            merged.transport = DiscoveryTransport::Auto;
+        }
+        merged.duplicate_data = filters.iter().any(|f| f.duplicate_data);
+        merged.pattern = filters[0].pattern.clone();
+        if filters.iter().any(|f| f.pattern != merged.pattern) {
+            merged.pattern = None;
+        }
+        merged
+    }
+
+    pub fn matches(&self, device: &FakeBluetoothDevice) -> Result<bool, BlurmockError> {
+        if let Some(ref pattern) = self.pattern {
+            let address_matches = device.get_address()?.starts_with(pattern.as_str());
+            let name_matches = device.get_name().map(|name| name.starts_with(pattern.as_str())).unwrap_or(false);
+            if !address_matches && !name_matches {
+                return Ok(false);
+            }
+        }
+
+        if !self.uuids.is_empty() {
+            let mut device_uuids = device.get_uuids()?;
+            if let Ok(service_data) = device.get_service_data() {
+                device_uuids.extend(service_data.keys().cloned());
+            }
+            let device_uuids: Vec<String> = device_uuids.iter().map(|u| normalize_uuid(u)).collect();
+            if !self.uuids.iter().any(|u| device_uuids.contains(&normalize_uuid(u))) {
+                return Ok(false);
+            }
+        }
+
+        if let Some(threshold) = self.rssi {
+            match device.get_rssi() {
+                Ok(rssi) if rssi >= threshold => (),
+                _ => return Ok(false),
+            }
+        }
+
+        if let Some(threshold) = self.pathloss {
+            match (device.get_tx_power(), device.get_rssi()) {
+                (Ok(tx_power), Ok(rssi)) if i32::from(tx_power) - i32::from(rssi) <= i32::from(threshold) => (),
+                _ => return Ok(false),
+            }
+        }
+        Ok(true)
+    }
+}
 
 #[derive(Debug)]
 pub struct FakeBluetoothDiscoverySession {
     adapter: Arc<FakeBluetoothAdapter>,
+    id: usize,
 }
 
 impl FakeBluetoothDiscoverySession {
-    pub fn create_session(adapter: Arc<FakeBluetoothAdapter>) -> Result<FakeBluetoothDiscoverySession, Box<Error>> {
-        Ok(FakeBluetoothDiscoverySession::new(adapter))
+    pub fn create_session(adapter: Arc<FakeBluetoothAdapter>) -> Result<FakeBluetoothDiscoverySession, BlurmockError> {
+        let id = adapter.allocate_discovery_session_id()?;
+        Ok(FakeBluetoothDiscoverySession::new(adapter, id))
     }
 
-    fn new(adapter: Arc<FakeBluetoothAdapter>) -> FakeBluetoothDiscoverySession {
+    fn new(adapter: Arc<FakeBluetoothAdapter>, id: usize) -> FakeBluetoothDiscoverySession {
         FakeBluetoothDiscoverySession {
-            adapter: adapter,
+            adapter,
+            id,
         }
     }
 
@@ -22,19 +164,89 @@ impl FakeBluetoothDiscoverySession {
         self.adapter.clone()
     }
 
-    pub fn start_discovery(&self) -> Result<(), Box<Error>> {
-        match self.adapter.get_can_start_discovery() {
-            Ok(false) => Err(Box::from("Failed to start discovery session")),
-            Ok(true) => Ok(()),
-            Err(err) => Err(err),
+    pub fn is_active(&self) -> Result<bool, BlurmockError> {
+        self.adapter.is_discovery_session_active(self.id)
+    }
+
+    /// Sets the filter this session scans with; `None` clears it. Like BlueZ,
+    /// an RSSI threshold and a pathloss threshold cannot be combined.
+    pub fn set_discovery_filter(&self, filter: Option<DiscoveryFilter>) -> Result<(), BlurmockError> {
+        if let Some(ref filter) = filter {
+            if filter.rssi.is_some() && filter.pathloss.is_some() {
+                return Err(BlurmockError::InvalidArguments);
+            }
         }
+        self.adapter.set_session_discovery_filter(self.id, filter)
+    }
+
+    pub fn get_discovery_filter(&self) -> Result<Option<DiscoveryFilter>, BlurmockError> {
+        self.adapter.get_session_discovery_filter(self.id)
     }
 
-    pub fn stop_discovery(&self) -> Result<(), Box<Error>> {
-        match self.adapter.get_can_stop_discovery() {
-            Ok(false) => Err(Box::from("Failed to stop discovery session")),
-            Ok(true) => Ok(()),
-            Err(err) => Err(err),
+    /// Chooses between active scanning, the default, and passive scanning.
+    /// Devices only show the fields of their scan response while an active
+    /// scan is running; fields already shown stay when switching to passive.
+    pub fn set_active_scanning(&self, active_scanning: bool) -> Result<(), BlurmockError> {
+        self.adapter.set_session_active_scanning(self.id, active_scanning)
+    }
+
+    pub fn is_active_scanning(&self) -> Result<bool, BlurmockError> {
+        self.adapter.is_session_active_scanning(self.id)
+    }
+
+    /// Returns the devices this session has discovered: the adapter's devices
+    /// that pass the session's own filter.
+    pub fn get_discovered_devices(&self) -> Result<Vec<Arc<FakeBluetoothDevice>>, BlurmockError> {
+        if !self.is_active()? {
+            return Err(BlurmockError::NotDiscovering);
         }
+        let filter = self.get_discovery_filter()?.unwrap_or_default();
+        let mut discovered_devices = vec![];
+        for device in self.adapter.get_devices()? {
+            if filter.matches(&device)? {
+                discovered_devices.push(device);
+            }
+        }
+        Ok(discovered_devices)
+    }
+
+    pub fn get_discovered_device_list(&self) -> Result<Vec<String>, BlurmockError> {
+        Ok(self.get_discovered_devices()?.into_iter().map(|d| d.get_id()).collect())
+    }
+
+    pub fn start_discovery(&self) -> Result<(), BlurmockError> {
+        self.adapter.begin_operation(Operation::StartDiscovery, &self.adapter.get_id())?;
+        if !self.adapter.is_present()? {
+            return Err(BlurmockError::AdapterNotPresent);
+        }
+        if !self.adapter.is_powered()? {
+            return Err(BlurmockError::AdapterNotPowered);
+        }
+        if self.is_active()? {
+            return Err(BlurmockError::InProgress);
+        }
+        if !self.adapter.get_can_start_discovery()? {
+            return Err(BlurmockError::StartDiscoveryFailed);
+        }
+        self.adapter.add_discovery_session(self.id)
+    }
+
+    pub fn stop_discovery(&self) -> Result<(), BlurmockError> {
+        self.adapter.begin_operation(Operation::StopDiscovery, &self.adapter.get_id())?;
+        if !self.is_active()? {
+            return Err(BlurmockError::NotDiscovering);
+        }
+        if !self.adapter.get_can_stop_discovery()? {
+            return Err(BlurmockError::StopDiscoveryFailed);
+        }
+        self.adapter.remove_discovery_session(self.id)
+    }
+}
+
+impl Drop for FakeBluetoothDiscoverySession {
+    fn drop(&mut self) {
+        let _ = self.adapter.remove_discovery_session(self.id);
+        let _ = self.adapter.set_session_discovery_filter(self.id, None);
+        let _ = self.adapter.set_session_active_scanning(self.id, true);
     }
 }
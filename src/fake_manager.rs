@@ -0,0 +1,246 @@
+use core::ops::Deref;
+use error::{BlurmockError, ObjectKind};
+use event::FakeBluetoothEvent;
+use fake_adapter::FakeBluetoothAdapter;
+use fake_characteristic::FakeBluetoothGATTCharacteristic;
+use fake_descriptor::FakeBluetoothGATTDescriptor;
+use fake_device::FakeBluetoothDevice;
+use fake_service::FakeBluetoothGATTService;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, Sender};
+
+/// Any object of the fake tree, as returned by `FakeBluetoothManager::find_object`.
+#[derive(Clone, Debug)]
+pub enum FakeBluetoothObject {
+    Adapter(Arc<FakeBluetoothAdapter>),
+    Device(Arc<FakeBluetoothDevice>),
+    Service(Arc<FakeBluetoothGATTService>),
+    Characteristic(Arc<FakeBluetoothGATTCharacteristic>),
+    Descriptor(Arc<FakeBluetoothGATTDescriptor>),
+}
+
+impl FakeBluetoothObject {
+    pub fn kind(&self) -> ObjectKind {
+        match *self {
+            FakeBluetoothObject::Adapter(_) => ObjectKind::Adapter,
+            FakeBluetoothObject::Device(_) => ObjectKind::Device,
+            FakeBluetoothObject::Service(_) => ObjectKind::Service,
+            FakeBluetoothObject::Characteristic(_) => ObjectKind::Characteristic,
+            FakeBluetoothObject::Descriptor(_) => ObjectKind::Descriptor,
+        }
+    }
+}
+
+/// The top-level object holding every adapter of the fake system, like the
+/// BlueZ object manager.
+#[derive(Clone, Debug)]
+pub struct FakeBluetoothManager {
+    adapters: Arc<Mutex<Vec<Arc<FakeBluetoothAdapter>>>>,
+    default_adapter: Arc<Mutex<Option<String>>>,
+    subscribers: Arc<Mutex<Vec<Sender<FakeBluetoothEvent>>>>,
+}
+
+impl FakeBluetoothManager {
+    pub fn new() -> Arc<FakeBluetoothManager> {
+        Arc::new(FakeBluetoothManager {
+            adapters: Arc::new(Mutex::new(vec![])),
+            default_adapter: Arc::new(Mutex::new(None)),
+            subscribers: Arc::new(Mutex::new(vec![])),
+        })
+    }
+
+    make_getter!(get_adapters, adapters, Vec<Arc<FakeBluetoothAdapter>>);
+
+    pub fn get_adapter_list(&self) -> Result<Vec<String>, BlurmockError> {
+        Ok(self.get_adapters()?.into_iter().map(|a| a.get_id()).collect())
+    }
+
+    pub fn get_adapter(&self, id: String) -> Result<Arc<FakeBluetoothAdapter>, BlurmockError> {
+        for adapter in self.get_adapters()? {
+            if adapter.get_id() == id {
+                return Ok(adapter);
+            }
+        }
+        Err(BlurmockError::NotFound { kind: ObjectKind::Adapter, id: Some(id) })
+    }
+
+    /// Plugs in an adapter and sends `AdapterAdded`. Fails with
+    /// `InvalidArguments` if an adapter with the same id is already plugged in.
+    pub fn add_adapter(&self, adapter: Arc<FakeBluetoothAdapter>) -> Result<(), BlurmockError> {
+        let adapter_id = adapter.get_id();
+        let cloned = self.adapters.clone();
+        let mut adapters = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        if adapters.iter().any(|a| a.get_id() == adapter_id) {
+            return Err(BlurmockError::InvalidArguments);
+        }
+        adapters.push(adapter);
+        drop(adapters);
+        self.emit_event(FakeBluetoothEvent::AdapterAdded { adapter_id });
+        Ok(())
+    }
+
+    /// Unplugs an adapter and sends `AdapterRemoved`. The adapter is marked
+    /// not present, so handles kept by the code under test start failing.
+    pub fn remove_adapter(&self, id: String) -> Result<(), BlurmockError> {
+        let cloned = self.adapters.clone();
+        let mut adapters = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        let index = match adapters.iter().position(|a| a.get_id() == id) {
+            Some(index) => index,
+            None => return Err(BlurmockError::NotFound { kind: ObjectKind::Adapter, id: Some(id) }),
+        };
+        let adapter = adapters.remove(index);
+        drop(adapters);
+
+        let cloned = self.default_adapter.clone();
+        match cloned.lock() {
+            Ok(mut default_adapter) => if default_adapter.as_ref() == Some(&id) {
+                *default_adapter = None;
+            },
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        }
+
+        adapter.set_present(false)?;
+        self.emit_event(FakeBluetoothEvent::AdapterRemoved { adapter_id: id });
+        Ok(())
+    }
+
+    /// Returns the adapter chosen with `set_default_adapter`, or the first
+    /// one plugged in.
+    pub fn get_default_adapter(&self) -> Result<Arc<FakeBluetoothAdapter>, BlurmockError> {
+        let cloned = self.default_adapter.clone();
+        let default_adapter = match cloned.lock() {
+            Ok(guard) => guard.deref().clone(),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        if let Some(id) = default_adapter {
+            return self.get_adapter(id);
+        }
+        match self.get_adapters()?.into_iter().next() {
+            Some(adapter) => Ok(adapter),
+            None => Err(BlurmockError::NotFound { kind: ObjectKind::Adapter, id: None }),
+        }
+    }
+
+    pub fn set_default_adapter(&self, id: String) -> Result<(), BlurmockError> {
+        self.get_adapter(id.clone())?;
+        let cloned = self.default_adapter.clone();
+        let mut default_adapter = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        *default_adapter = Some(id);
+        Ok(())
+    }
+
+    /// Looks up an adapter, device, service, characteristic or descriptor by
+    /// id across all adapters. GATT objects are found even while their
+    /// device is disconnected. Fails with `NotFound` for `kind` if no object
+    /// of that kind has the id, and with `InvalidArguments` for `AdData`.
+    pub fn find_object(&self, kind: ObjectKind, id: &str) -> Result<FakeBluetoothObject, BlurmockError> {
+        if kind == ObjectKind::AdData {
+            return Err(BlurmockError::InvalidArguments);
+        }
+        for adapter in self.get_adapters()? {
+            if kind == ObjectKind::Adapter {
+                if adapter.get_id() == id {
+                    return Ok(FakeBluetoothObject::Adapter(adapter));
+                }
+                continue;
+            }
+            for device in adapter.get_devices()? {
+                if kind == ObjectKind::Device {
+                    if device.get_id() == id {
+                        return Ok(FakeBluetoothObject::Device(device));
+                    }
+                    continue;
+                }
+                for service in device.get_gatt_service_list()? {
+                    if kind == ObjectKind::Service {
+                        if service.get_id() == id {
+                            return Ok(FakeBluetoothObject::Service(service));
+                        }
+                        continue;
+                    }
+                    for characteristic in service.get_gatt_characteristic_list()? {
+                        if kind == ObjectKind::Characteristic {
+                            if characteristic.get_id() == id {
+                                return Ok(FakeBluetoothObject::Characteristic(characteristic));
+                            }
+                            continue;
+                        }
+                        for descriptor in characteristic.get_gatt_descriptor_list()? {
+                            if descriptor.get_id() == id {
+                                return Ok(FakeBluetoothObject::Descriptor(descriptor));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Err(BlurmockError::NotFound { kind, id: Some(String::from(id)) })
+    }
+
+    /// Returns a receiver for the `AdapterAdded` and `AdapterRemoved` events.
+    /// Events of the adapters themselves are sent by `FakeBluetoothAdapter::subscribe`.
+    pub fn subscribe(&self) -> Result<Receiver<FakeBluetoothEvent>, BlurmockError> {
+        let (sender, receiver) = mpsc::channel();
+        let cloned = self.subscribers.clone();
+        let mut subscribers = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        subscribers.push(sender);
+        Ok(receiver)
+    }
+
+    fn emit_event(&self, event: FakeBluetoothEvent) {
+        let cloned = self.subscribers.clone();
+        let mut subscribers = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return,
+        };
+        subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use error::{BlurmockError, ObjectKind};
+    use fake_adapter::FakeBluetoothAdapter;
+    use super::FakeBluetoothManager;
+
+    #[test]
+    fn find_object_reports_the_missing_kind() {
+        let manager = FakeBluetoothManager::new();
+        let adapter = FakeBluetoothAdapter::builder()
+            .id("hci0")
+            .device("dev", |d| {
+                d.service("0000180f-0000-1000-8000-00805f9b34fb", |s| {
+                    s.id("battery").characteristic("00002a19-0000-1000-8000-00805f9b34fb", |c| {
+                        c.id("level").descriptor("00002902-0000-1000-8000-00805f9b34fb", |d| d.id("config"))
+                    })
+                })
+            })
+            .build();
+        manager.add_adapter(adapter).unwrap();
+
+        for &(kind, id) in &[(ObjectKind::Adapter, "hci0"),
+                             (ObjectKind::Device, "dev"),
+                             (ObjectKind::Service, "battery"),
+                             (ObjectKind::Characteristic, "level"),
+                             (ObjectKind::Descriptor, "config")] {
+            assert_eq!(manager.find_object(kind, id).map(|o| o.kind()), Ok(kind));
+            assert_eq!(manager.find_object(kind, "missing").err(),
+                       Some(BlurmockError::NotFound { kind, id: Some(String::from("missing")) }));
+        }
+        assert_eq!(manager.find_object(ObjectKind::Service, "level").err(),
+                   Some(BlurmockError::NotFound { kind: ObjectKind::Service, id: Some(String::from("level")) }));
+        assert_eq!(manager.find_object(ObjectKind::AdData, "hci0").err(), Some(BlurmockError::InvalidArguments));
+    }
+}
@@ -1,7 +1,11 @@
 use core::ops::Deref;
-use fake_characteristic::FakeBluetoothGATTCharacteristic;
+use error::{BlurmockError, ObjectKind};
+use event::{FakeBluetoothEvent, PropertyValue};
+use fake_characteristic::{FakeBluetoothGATTCharacteristic, FakeBluetoothGATTCharacteristicBuilder};
 use fake_device::FakeBluetoothDevice;
-use std::error::Error;
+use free_child_id;
+use operation::Operation;
+use snapshot::copy_value;
 use std::sync::{Arc, Mutex};
 
 #[derive(Clone, Debug)]
@@ -22,7 +26,7 @@ impl FakeBluetoothGATTService {
                included_services: Vec<Arc<FakeBluetoothGATTService>>,
                uuid: String)
                -> Arc<FakeBluetoothGATTService> {
-        if let Ok(existing_service) = device.get_gatt_service(id.clone()) {
+        if let Ok(existing_service) = device.find_gatt_service(id.clone()) {
             return existing_service;
         }
         let service = Arc::new(FakeBluetoothGATTService {
@@ -54,85 +58,317 @@ impl FakeBluetoothGATTService {
 
     make_setter!(set_id, id);
 
-    make_getter!(get_gatt_characteristic_structs, gatt_characteristics, Vec<Arc<FakeBluetoothGATTCharacteristic>>);
-
     make_getter!(is_primary);
 
     make_setter!(set_is_primary, is_primary, bool);
 
-    make_setter!(set_includes, included_services, Vec<Arc<FakeBluetoothGATTService>>);
-
     make_getter!(get_uuid, uuid, String);
 
     make_setter!(set_uuid, uuid, String);
 
-    pub fn get_device(&self) -> Result<Arc<FakeBluetoothDevice>, Box<Error>> {
+    pub fn get_device(&self) -> Result<Arc<FakeBluetoothDevice>, BlurmockError> {
         Ok(self.device.clone())
     }
 
-    pub fn get_gatt_characteristics(&self) -> Result<Vec<String>, Box<Error>> {
+    pub fn check_connected(&self) -> Result<(), BlurmockError> {
+        self.device.check_connected()
+    }
+
+    pub(crate) fn begin_operation(&self, operation: Operation, object_id: &str) -> Result<(), BlurmockError> {
+        self.device.begin_operation(operation, object_id)
+    }
+
+    pub(crate) fn emit_event(&self, event: FakeBluetoothEvent) {
+        self.device.emit_event(event)
+    }
+
+    fn emit_property_changed(&self, property: &'static str, old: PropertyValue, new: PropertyValue) {
+        self.emit_event(FakeBluetoothEvent::PropertyChanged {
+            kind: ObjectKind::Service,
+            object_id: self.get_id(),
+            property,
+            old,
+            new,
+        });
+    }
+
+    pub fn get_gatt_characteristics(&self) -> Result<Vec<String>, BlurmockError> {
+        let gatt_characteristics = self.get_gatt_characteristic_structs()?;
+        Ok(gatt_characteristics.into_iter().map(|s| s.get_id()).collect())
+    }
+
+    pub fn get_gatt_characteristic_structs(&self) -> Result<Vec<Arc<FakeBluetoothGATTCharacteristic>>, BlurmockError> {
+        self.begin_operation(Operation::GetGattCharacteristics, &self.get_id())?;
+        self.check_connected()?;
+        self.get_gatt_characteristic_list()
+    }
+
+    /// Like `get_gatt_characteristic_structs`, but also works while the device is disconnected.
+    /// Used internally to maintain the GATT tree.
+    pub(crate) fn get_gatt_characteristic_list(&self) -> Result<Vec<Arc<FakeBluetoothGATTCharacteristic>>, BlurmockError> {
         let cloned = self.gatt_characteristics.clone();
         let gatt_characteristics = match cloned.lock() {
             Ok(guard) => guard.deref().clone(),
-            Err(_) => return Err(Box::from("Could not get the value.")),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
         };
-        Ok(gatt_characteristics.into_iter().map(|s| s.get_id()).collect())
+        Ok(gatt_characteristics)
+    }
+
+    pub fn get_gatt_characteristic(&self, id: String) -> Result<Arc<FakeBluetoothGATTCharacteristic>, BlurmockError> {
+        self.begin_operation(Operation::GetGattCharacteristics, &self.get_id())?;
+        self.check_connected()?;
+        self.find_gatt_characteristic(id)
     }
 
-    pub fn get_gatt_characteristic(&self, id: String) -> Result<Arc<FakeBluetoothGATTCharacteristic>, Box<Error>> {
-        let characteristics = try!(self.get_gatt_characteristic_structs());
-        for characteristic in characteristics {
-            let characteristic_id = characteristic.get_id();
-            if characteristic_id == id {
+    pub(crate) fn find_gatt_characteristic(&self, id: String) -> Result<Arc<FakeBluetoothGATTCharacteristic>, BlurmockError> {
+        for characteristic in self.get_gatt_characteristic_list()? {
+            if characteristic.get_id() == id {
                 return Ok(characteristic);
             }
         }
-        Err(Box::from("No characteristic exists with the given id."))
+        Err(BlurmockError::NotFound { kind: ObjectKind::Characteristic, id: Some(id) })
     }
 
-    pub fn add_characteristic(&self, characteristic: Arc<FakeBluetoothGATTCharacteristic>) -> Result<(), Box<Error>> {
+    pub fn add_characteristic(&self, characteristic: Arc<FakeBluetoothGATTCharacteristic>) -> Result<(), BlurmockError> {
         let cloned = self.gatt_characteristics.clone();
         let mut gatt_characteristics = match cloned.lock() {
             Ok(guard) => guard,
-            Err(_) => return Err(Box::from("Could not get the value.")),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
         };
-        Ok(gatt_characteristics.push(characteristic))
+        let characteristic_id = characteristic.get_id();
+        gatt_characteristics.push(characteristic);
+        drop(gatt_characteristics);
+        self.emit_event(FakeBluetoothEvent::CharacteristicAdded {
+            service_id: self.get_id(),
+            characteristic_id,
+        });
+        Ok(())
     }
 
-    pub fn remove_characteristic(&self, id: String) -> Result<(), Box<Error>> {
+    pub fn remove_characteristic(&self, id: String) -> Result<(), BlurmockError> {
         let cloned = self.gatt_characteristics.clone();
         let mut gatt_characteristics = match cloned.lock() {
             Ok(guard) => guard,
-            Err(_) => return Err(Box::from("Could not get the value.")),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
         };
-        Ok(gatt_characteristics.retain(|c| c.get_id() != id))
+        let count = gatt_characteristics.len();
+        gatt_characteristics.retain(|c| c.get_id() != id);
+        let removed = gatt_characteristics.len() != count;
+        drop(gatt_characteristics);
+        if removed {
+            self.emit_event(FakeBluetoothEvent::CharacteristicRemoved {
+                service_id: self.get_id(),
+                characteristic_id: id,
+            });
+        }
+        Ok(())
     }
 
-    pub fn add_included_service(&self, service: Arc<FakeBluetoothGATTService>) -> Result<(), Box<Error>> {
+    pub fn add_included_service(&self, service: Arc<FakeBluetoothGATTService>) -> Result<(), BlurmockError> {
         let cloned = self.included_services.clone();
         let mut included_services = match cloned.lock() {
-            Ok(guard) => guard,
-            Err(_) => return Err(Box::from("Could not get the value.")),
+            Ok(guard) => guard.deref().clone(),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        included_services.push(service);
+        self.set_includes(included_services)
+    }
+
+    pub fn remove_included_service(&self, id: String) -> Result<(), BlurmockError> {
+        let cloned = self.included_services.clone();
+        let mut included_services = match cloned.lock() {
+            Ok(guard) => guard.deref().clone(),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
         };
-        Ok(included_services.push(service))
+        included_services.retain(|i| i.get_id() != id);
+        self.set_includes(included_services)
     }
 
-    pub fn remove_included_service(&self, id: String) -> Result<(), Box<Error>> {
+    pub fn set_includes(&self, services: Vec<Arc<FakeBluetoothGATTService>>) -> Result<(), BlurmockError> {
+        let new_ids: Vec<String> = services.iter().map(|s| s.get_id()).collect();
         let cloned = self.included_services.clone();
         let mut included_services = match cloned.lock() {
             Ok(guard) => guard,
-            Err(_) => return Err(Box::from("Could not get the value.")),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
         };
-        Ok(included_services.retain(|i| i.get_id() != id))
+        let old_services = ::std::mem::replace(&mut *included_services, services);
+        drop(included_services);
+        let old_ids: Vec<String> = old_services.iter().map(|s| s.get_id()).collect();
+        if old_ids != new_ids {
+            self.emit_property_changed("included_services", old_ids.into(), new_ids.into());
+        }
+        Ok(())
     }
 
-    pub fn get_includes(&self) -> Result<Vec<String>, Box<Error>> {
+    pub fn get_includes(&self) -> Result<Vec<String>, BlurmockError> {
+        self.begin_operation(Operation::GetIncludes, &self.get_id())?;
+        self.check_connected()?;
         let cloned = self.included_services.clone();
         let included_services = match cloned.lock() {
             Ok(guard) => guard.deref().clone(),
-            Err(_) => return Err(Box::from("Could not get the value.")),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
         };
         Ok(included_services.into_iter().map(|s| s.get_id()).collect())
     }
 
+    // The included services as stored, which scenarios save whether or not the
+    // device is connected.
+    #[cfg(feature = "scenario")]
+    pub(crate) fn get_included_services(&self) -> Result<Vec<Arc<FakeBluetoothGATTService>>, BlurmockError> {
+        match self.included_services.lock() {
+            Ok(guard) => Ok(guard.deref().clone()),
+            Err(_) => Err(BlurmockError::LockPoisoned),
+        }
+    }
+
+    // A copy of the service and its characteristics belonging to `device`,
+    // which is not told about it. Included services still point to the
+    // originals until `retarget_included_services` is called.
+    pub(crate) fn deep_copy(&self, device: Arc<FakeBluetoothDevice>) -> Result<Arc<FakeBluetoothGATTService>, BlurmockError> {
+        let service = Arc::new(FakeBluetoothGATTService {
+            id: copy_value(&self.id)?,
+            device,
+            gatt_characteristics: Arc::new(Mutex::new(vec![])),
+            is_primary: copy_value(&self.is_primary)?,
+            included_services: copy_value(&self.included_services)?,
+            uuid: copy_value(&self.uuid)?,
+        });
+        let characteristics = self.get_gatt_characteristic_list()?
+                                  .iter()
+                                  .map(|characteristic| characteristic.deep_copy(service.clone()))
+                                  .collect::<Result<Vec<_>, _>>()?;
+        match service.gatt_characteristics.lock() {
+            Ok(mut gatt_characteristics) => *gatt_characteristics = characteristics,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        }
+        Ok(service)
+    }
+
+    // Replaces the included services by the copies among `services` with the
+    // same id.
+    pub(crate) fn retarget_included_services(&self, services: &[Arc<FakeBluetoothGATTService>]) -> Result<(), BlurmockError> {
+        let cloned = self.included_services.clone();
+        let mut included_services = match cloned.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        for included_service in included_services.iter_mut() {
+            let id = included_service.get_id();
+            if let Some(copy) = services.iter().find(|s| s.get_id() == id) {
+                *included_service = copy.clone();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FakeBluetoothGATTServiceBuilder {
+    id: Option<String>,
+    uuid: String,
+    is_primary: bool,
+    gatt_characteristics: Vec<FakeBluetoothGATTCharacteristicBuilder>,
+    included_services: Vec<Arc<FakeBluetoothGATTService>>,
+}
+
+impl FakeBluetoothGATTServiceBuilder {
+    pub fn new<S: Into<String>>(uuid: S) -> FakeBluetoothGATTServiceBuilder {
+        FakeBluetoothGATTServiceBuilder {
+            id: None,
+            uuid: uuid.into(),
+            is_primary: true,
+            gatt_characteristics: vec![],
+            included_services: vec![],
+        }
+    }
+
+    /// Overrides the generated `<device id>/serviceXXXX` id.
+    pub fn id<S: Into<String>>(mut self, id: S) -> FakeBluetoothGATTServiceBuilder {
+        self.id = Some(id.into());
+        self
+    }
+
+    pub fn primary(mut self, is_primary: bool) -> FakeBluetoothGATTServiceBuilder {
+        self.is_primary = is_primary;
+        self
+    }
+
+    pub fn included_service(mut self, service: Arc<FakeBluetoothGATTService>) -> FakeBluetoothGATTServiceBuilder {
+        self.included_services.push(service);
+        self
+    }
+
+    pub fn characteristic<S, F>(mut self, uuid: S, build: F) -> FakeBluetoothGATTServiceBuilder
+        where S: Into<String>,
+              F: FnOnce(FakeBluetoothGATTCharacteristicBuilder) -> FakeBluetoothGATTCharacteristicBuilder
+    {
+        self.gatt_characteristics.push(build(FakeBluetoothGATTCharacteristicBuilder::new(uuid)));
+        self
+    }
+
+    /// Creates the service with all of its characteristics and descriptors and
+    /// registers it on `device`.
+    pub fn build(self, device: Arc<FakeBluetoothDevice>) -> Arc<FakeBluetoothGATTService> {
+        let id = match self.id {
+            Some(id) => id,
+            None => {
+                let ids: Vec<String> = device.get_gatt_service_list()
+                                             .map(|services| services.iter().map(|s| s.get_id()).collect())
+                                             .unwrap_or_default();
+                free_child_id(&format!("{}/service", device.get_id()), &ids)
+            },
+        };
+        let service = FakeBluetoothGATTService::new(id,
+                                                    device,
+                                                    vec!(),
+                                                    self.is_primary,
+                                                    self.included_services,
+                                                    self.uuid);
+        for characteristic in self.gatt_characteristics {
+            characteristic.build(service.clone());
+        }
+        service
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fake_adapter::FakeBluetoothAdapter;
+    use fake_characteristic::FakeBluetoothGATTCharacteristicBuilder;
+    use fake_descriptor::FakeBluetoothGATTDescriptorBuilder;
+    use super::FakeBluetoothGATTServiceBuilder;
+
+    #[test]
+    fn generated_ids_are_not_taken_after_a_removal() {
+        let adapter = FakeBluetoothAdapter::builder().build();
+        let device = adapter.device("dev")
+                            .service("0000180a-0000-1000-8000-00805f9b34fb", |s| s)
+                            .service("0000180f-0000-1000-8000-00805f9b34fb", |s| s)
+                            .build();
+        device.remove_service(String::from("dev/service0000")).unwrap();
+        let service = FakeBluetoothGATTServiceBuilder::new("00001800-0000-1000-8000-00805f9b34fb")
+            .characteristic("00002a00-0000-1000-8000-00805f9b34fb", |c| c)
+            .build(device.clone());
+        assert_eq!(service.get_id(), "dev/service0002");
+        assert_eq!(service.get_uuid(), Ok(String::from("00001800-0000-1000-8000-00805f9b34fb")));
+        let services = device.get_gatt_service_list().unwrap();
+        assert_eq!(services.len(), 2);
+        let old_service = services.iter().find(|s| s.get_id() == "dev/service0001").unwrap();
+        assert_eq!(old_service.get_gatt_characteristic_list().unwrap().len(), 0);
+        assert_eq!(service.get_gatt_characteristic_list().unwrap().len(), 1);
+
+        FakeBluetoothGATTCharacteristicBuilder::new("00002a01-0000-1000-8000-00805f9b34fb").build(service.clone());
+        service.remove_characteristic(String::from("dev/service0002/char0000")).unwrap();
+        let characteristic = FakeBluetoothGATTCharacteristicBuilder::new("00002a02-0000-1000-8000-00805f9b34fb")
+            .build(service.clone());
+        assert_eq!(characteristic.get_id(), "dev/service0002/char0002");
+
+        FakeBluetoothGATTDescriptorBuilder::new("00002901-0000-1000-8000-00805f9b34fb").build(characteristic.clone());
+        FakeBluetoothGATTDescriptorBuilder::new("00002902-0000-1000-8000-00805f9b34fb").build(characteristic.clone());
+        characteristic.remove_descriptor(String::from("dev/service0002/char0002/desc0000")).unwrap();
+        let descriptor = FakeBluetoothGATTDescriptorBuilder::new("00002903-0000-1000-8000-00805f9b34fb")
+            .build(characteristic.clone());
+        assert_eq!(descriptor.get_id(), "dev/service0002/char0002/desc0002");
+        assert_eq!(characteristic.get_gatt_descriptor_list().unwrap().len(), 2);
+    }
 }
@@ -0,0 +1,217 @@
+use error::BlurmockError;
+use operation::Operation;
+use std::sync::{Arc, Mutex};
+
+/// When a `FaultRule` makes a matching call fail.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FaultTrigger {
+    Always,
+    /// The next `n` matching calls fail, then the rule is removed.
+    Next(u32),
+    /// Every `n`th matching call fails, counting from the rule's creation.
+    EveryNth(u32),
+    /// Each matching call fails with the given probability. The draws come
+    /// from a generator seeded with `seed`, so a run can be repeated.
+    Probability { probability: f64, seed: u64 },
+}
+
+/// Makes calls of `operation` fail with `error`. Without a target the rule
+/// applies to every object, otherwise only to the object with that id.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FaultRule {
+    pub operation: Operation,
+    pub target: Option<String>,
+    pub error: BlurmockError,
+    pub trigger: FaultTrigger,
+}
+
+impl FaultRule {
+    pub fn new(operation: Operation, error: BlurmockError) -> FaultRule {
+        FaultRule {
+            operation,
+            target: None,
+            error,
+            trigger: FaultTrigger::Always,
+        }
+    }
+
+    pub fn on<S: Into<String>>(mut self, target: S) -> FaultRule {
+        self.target = Some(target.into());
+        self
+    }
+
+    pub fn next(mut self, count: u32) -> FaultRule {
+        self.trigger = FaultTrigger::Next(count);
+        self
+    }
+
+    pub fn every_nth(mut self, n: u32) -> FaultRule {
+        self.trigger = FaultTrigger::EveryNth(n);
+        self
+    }
+
+    pub fn with_probability(mut self, probability: f64, seed: u64) -> FaultRule {
+        self.trigger = FaultTrigger::Probability { probability, seed };
+        self
+    }
+
+    pub(crate) fn matches(&self, operation: Operation, target: &str) -> bool {
+        self.operation == operation && self.target.as_ref().map_or(true, |t| t == target)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FaultId(u64);
+
+#[derive(Clone, Debug)]
+struct ActiveFault {
+    id: FaultId,
+    rule: FaultRule,
+    calls: u64,
+    rng: SplitMix64,
+}
+
+impl ActiveFault {
+    // Counts the call and tells whether it fails.
+    fn fires(&mut self) -> bool {
+        self.calls += 1;
+        match self.rule.trigger {
+            FaultTrigger::Always => true,
+            FaultTrigger::Next(count) => self.calls <= u64::from(count),
+            FaultTrigger::EveryNth(n) => n != 0 && self.calls % u64::from(n) == 0,
+            FaultTrigger::Probability { probability, .. } => self.rng.next_f64() < probability,
+        }
+    }
+
+    fn is_exhausted(&self) -> bool {
+        match self.rule.trigger {
+            FaultTrigger::Next(count) => self.calls >= u64::from(count),
+            _ => false,
+        }
+    }
+}
+
+/// A small seeded generator, so that probabilistic faults are reproducible
+/// without pulling in a random number crate.
+#[derive(Clone, Debug)]
+pub(crate) struct SplitMix64(u64);
+
+impl SplitMix64 {
+    pub(crate) fn new(seed: u64) -> SplitMix64 {
+        SplitMix64(seed)
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `[0, 1)`.
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// The fault rules of an adapter tree. Clones share the same rules.
+#[derive(Clone, Debug, Default)]
+pub struct FaultInjector {
+    faults: Arc<Mutex<Vec<ActiveFault>>>,
+    next_fault_id: Arc<Mutex<u64>>,
+}
+
+impl FaultInjector {
+    pub fn new() -> FaultInjector {
+        FaultInjector::default()
+    }
+
+    pub fn add_rule(&self, rule: FaultRule) -> Result<FaultId, BlurmockError> {
+        let id = match self.next_fault_id.lock() {
+            Ok(mut next_fault_id) => {
+                *next_fault_id += 1;
+                FaultId(*next_fault_id)
+            },
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        let seed = match rule.trigger {
+            FaultTrigger::Probability { seed, .. } => seed,
+            _ => 0,
+        };
+        let mut faults = match self.faults.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        faults.push(ActiveFault {
+            id,
+            rule,
+            calls: 0,
+            rng: SplitMix64::new(seed),
+        });
+        Ok(id)
+    }
+
+    /// Returns false if the rule was already removed or used up.
+    pub fn remove_rule(&self, id: FaultId) -> Result<bool, BlurmockError> {
+        let mut faults = match self.faults.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        let count = faults.len();
+        faults.retain(|f| f.id != id);
+        Ok(faults.len() != count)
+    }
+
+    pub fn get_rules(&self) -> Result<Vec<FaultRule>, BlurmockError> {
+        match self.faults.lock() {
+            Ok(faults) => Ok(faults.iter().map(|f| f.rule.clone()).collect()),
+            Err(_) => Err(BlurmockError::LockPoisoned),
+        }
+    }
+
+    pub fn clear(&self) -> Result<(), BlurmockError> {
+        match self.faults.lock() {
+            Ok(mut faults) => faults.clear(),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        }
+        Ok(())
+    }
+
+    // Replaces the rules by copies of those of `source`, keeping their call
+    // counts and random state.
+    pub(crate) fn copy_from(&self, source: &FaultInjector) -> Result<(), BlurmockError> {
+        let (faults, next_fault_id) = match (source.faults.lock(), source.next_fault_id.lock()) {
+            (Ok(faults), Ok(next_fault_id)) => (faults.clone(), *next_fault_id),
+            _ => return Err(BlurmockError::LockPoisoned),
+        };
+        match (self.faults.lock(), self.next_fault_id.lock()) {
+            (Ok(mut target_faults), Ok(mut target_next_fault_id)) => {
+                *target_faults = faults;
+                *target_next_fault_id = next_fault_id;
+            },
+            _ => return Err(BlurmockError::LockPoisoned),
+        }
+        Ok(())
+    }
+
+    /// Counts a call of `operation` on the object `target` against every
+    /// matching rule and returns the error of the first rule that fires.
+    pub fn check(&self, operation: Operation, target: &str) -> Result<(), BlurmockError> {
+        let mut faults = match self.faults.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        let mut error = None;
+        for fault in faults.iter_mut().filter(|f| f.rule.matches(operation, target)) {
+            if fault.fires() && error.is_none() {
+                error = Some(fault.rule.error.clone());
+            }
+        }
+        faults.retain(|f| !f.is_exhausted());
+        match error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use error::BlurmockError;
+use std::ops::{BitOr, BitOrAssign};
+
+macro_rules! make_flag_set(
+    ($name: ident, $names: ident, $(($flag: ident, $bit: expr, $string: expr)),+) => {
+        #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+        pub struct $name(u32);
+
+        const $names: &[(&str, $name)] = &[$(($string, $name::$flag)),+];
+
+        impl $name {
+            $(pub const $flag: $name = $name(1 << $bit);)+
+
+            pub fn empty() -> $name {
+                $name(0)
+            }
+
+            pub fn is_empty(&self) -> bool {
+                self.0 == 0
+            }
+
+            pub fn contains(&self, other: $name) -> bool {
+                self.0 & other.0 == other.0
+            }
+
+            /// Returns true if at least one flag of `other` is set.
+            pub fn intersects(&self, other: $name) -> bool {
+                self.0 & other.0 != 0
+            }
+
+            pub fn insert(&mut self, other: $name) {
+                self.0 |= other.0;
+            }
+
+            pub fn remove(&mut self, other: $name) {
+                self.0 &= !other.0;
+            }
+
+            /// Parses BlueZ flag strings such as `"read"` or `"encrypt-write"`.
+            pub fn from_strings<I, S>(flags: I) -> Result<$name, BlurmockError>
+                where I: IntoIterator<Item = S>,
+                      S: AsRef<str>
+            {
+                let mut result = $name::empty();
+                for flag in flags {
+                    let flag = flag.as_ref();
+                    match $names.iter().find(|&&(name, _)| name == flag) {
+                        Some(&(_, value)) => result.insert(value),
+                        None => return Err(BlurmockError::InvalidFlag(String::from(flag))),
+                    }
+                }
+                Ok(result)
+            }
+
+            /// Returns the BlueZ flag strings, in the order BlueZ lists them.
+            pub fn to_strings(&self) -> Vec<String> {
+                $names.iter()
+                      .filter(|&&(_, value)| self.contains(value))
+                      .map(|&(name, _)| String::from(name))
+                      .collect()
+            }
+        }
+
+        impl BitOr for $name {
+            type Output = $name;
+
+            fn bitor(self, other: $name) -> $name {
+                $name(self.0 | other.0)
+            }
+        }
+
+        impl BitOrAssign for $name {
+            fn bitor_assign(&mut self, other: $name) {
+                self.0 |= other.0;
+            }
+        }
+    };
+);
+
+// The flags of BlueZ `GattCharacteristic1.Flags`.
+make_flag_set!(CharacteristicFlags, CHARACTERISTIC_FLAG_NAMES,
+               (BROADCAST, 0, "broadcast"),
+               (READ, 1, "read"),
+               (WRITE_WITHOUT_RESPONSE, 2, "write-without-response"),
+               (WRITE, 3, "write"),
+               (NOTIFY, 4, "notify"),
+               (INDICATE, 5, "indicate"),
+               (AUTHENTICATED_SIGNED_WRITES, 6, "authenticated-signed-writes"),
+               (EXTENDED_PROPERTIES, 7, "extended-properties"),
+               (RELIABLE_WRITE, 8, "reliable-write"),
+               (WRITABLE_AUXILIARIES, 9, "writable-auxiliaries"),
+               (ENCRYPT_READ, 10, "encrypt-read"),
+               (ENCRYPT_WRITE, 11, "encrypt-write"),
+               (ENCRYPT_AUTHENTICATED_READ, 12, "encrypt-authenticated-read"),
+               (ENCRYPT_AUTHENTICATED_WRITE, 13, "encrypt-authenticated-write"),
+               (SECURE_READ, 14, "secure-read"),
+               (SECURE_WRITE, 15, "secure-write"),
+               (AUTHORIZE, 16, "authorize"));
+
+impl CharacteristicFlags {
+    pub fn can_read(&self) -> bool {
+        self.contains(CharacteristicFlags::READ)
+    }
+
+    pub fn can_write(&self) -> bool {
+        self.intersects(CharacteristicFlags::WRITE |
+                        CharacteristicFlags::WRITE_WITHOUT_RESPONSE |
+                        CharacteristicFlags::AUTHENTICATED_SIGNED_WRITES |
+                        CharacteristicFlags::RELIABLE_WRITE)
+    }
+
+    pub fn can_notify(&self) -> bool {
+        self.intersects(CharacteristicFlags::NOTIFY | CharacteristicFlags::INDICATE)
+    }
+
+    /// Reading needs an encrypted (paired) link.
+    pub fn read_requires_pairing(&self) -> bool {
+        self.intersects(CharacteristicFlags::ENCRYPT_READ |
+                        CharacteristicFlags::ENCRYPT_AUTHENTICATED_READ |
+                        CharacteristicFlags::SECURE_READ)
+    }
+
+    /// Writing needs an encrypted (paired) link.
+    pub fn write_requires_pairing(&self) -> bool {
+        self.intersects(CharacteristicFlags::ENCRYPT_WRITE |
+                        CharacteristicFlags::ENCRYPT_AUTHENTICATED_WRITE |
+                        CharacteristicFlags::SECURE_WRITE)
+    }
+}
+
+// The flags of BlueZ `GattDescriptor1.Flags`.
+make_flag_set!(DescriptorFlags, DESCRIPTOR_FLAG_NAMES,
+               (READ, 0, "read"),
+               (WRITE, 1, "write"),
+               (ENCRYPT_READ, 2, "encrypt-read"),
+               (ENCRYPT_WRITE, 3, "encrypt-write"),
+               (ENCRYPT_AUTHENTICATED_READ, 4, "encrypt-authenticated-read"),
+               (ENCRYPT_AUTHENTICATED_WRITE, 5, "encrypt-authenticated-write"),
+               (SECURE_READ, 6, "secure-read"),
+               (SECURE_WRITE, 7, "secure-write"),
+               (AUTHORIZE, 8, "authorize"));
+
+impl DescriptorFlags {
+    pub fn can_read(&self) -> bool {
+        self.contains(DescriptorFlags::READ)
+    }
+
+    pub fn can_write(&self) -> bool {
+        self.contains(DescriptorFlags::WRITE)
+    }
+
+    /// Reading needs an encrypted (paired) link.
+    pub fn read_requires_pairing(&self) -> bool {
+        self.intersects(DescriptorFlags::ENCRYPT_READ |
+                        DescriptorFlags::ENCRYPT_AUTHENTICATED_READ |
+                        DescriptorFlags::SECURE_READ)
+    }
+
+    /// Writing needs an encrypted (paired) link.
+    pub fn write_requires_pairing(&self) -> bool {
+        self.intersects(DescriptorFlags::ENCRYPT_WRITE |
+                        DescriptorFlags::ENCRYPT_AUTHENTICATED_WRITE |
+                        DescriptorFlags::SECURE_WRITE)
+    }
+}
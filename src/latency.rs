@@ -0,0 +1,160 @@
+use error::BlurmockError;
+use fault::SplitMix64;
+use operation::Operation;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How long an operation takes, in clock time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Latency {
+    Fixed(Duration),
+    /// Uniformly distributed between `min` and `max`.
+    Uniform { min: Duration, max: Duration, seed: u64 },
+    /// Normally distributed, negative draws counting as no delay.
+    Normal { mean: Duration, std_dev: Duration, seed: u64 },
+}
+
+impl Latency {
+    fn seed(&self) -> u64 {
+        match *self {
+            Latency::Fixed(_) => 0,
+            Latency::Uniform { seed, .. } | Latency::Normal { seed, .. } => seed,
+        }
+    }
+}
+
+/// Delays calls of `operation`. Without a target the rule applies to every
+/// object, otherwise only to the object with that id.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LatencyRule {
+    pub operation: Operation,
+    pub target: Option<String>,
+    pub latency: Latency,
+}
+
+impl LatencyRule {
+    pub fn new(operation: Operation, latency: Latency) -> LatencyRule {
+        LatencyRule {
+            operation,
+            target: None,
+            latency,
+        }
+    }
+
+    pub fn on<S: Into<String>>(mut self, target: S) -> LatencyRule {
+        self.target = Some(target.into());
+        self
+    }
+}
+
+#[derive(Clone, Debug)]
+struct ActiveLatency {
+    rule: LatencyRule,
+    rng: SplitMix64,
+}
+
+impl ActiveLatency {
+    fn draw(&mut self) -> Duration {
+        match self.rule.latency {
+            Latency::Fixed(delay) => delay,
+            Latency::Uniform { min, max, .. } => {
+                if max <= min {
+                    return min;
+                }
+                min + (max - min).mul_f64(self.rng.next_f64())
+            },
+            Latency::Normal { mean, std_dev, .. } => {
+                // Box-Muller transform.
+                let u1 = 1.0 - self.rng.next_f64();
+                let u2 = self.rng.next_f64();
+                let z = (-2.0 * u1.ln()).sqrt() * (2.0 * ::std::f64::consts::PI * u2).cos();
+                let secs = mean.as_secs_f64() + z * std_dev.as_secs_f64();
+                if secs > 0.0 { Duration::from_secs_f64(secs) } else { Duration::from_secs(0) }
+            },
+        }
+    }
+}
+
+/// The latency rules of an adapter tree. Clones share the same rules.
+#[derive(Clone, Debug, Default)]
+pub struct LatencyInjector {
+    latencies: Arc<Mutex<Vec<ActiveLatency>>>,
+}
+
+impl LatencyInjector {
+    pub fn new() -> LatencyInjector {
+        LatencyInjector::default()
+    }
+
+    /// Adds the rule, replacing any rule for the same operation and target.
+    pub fn set_rule(&self, rule: LatencyRule) -> Result<(), BlurmockError> {
+        let mut latencies = match self.latencies.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        latencies.retain(|l| l.rule.operation != rule.operation || l.rule.target != rule.target);
+        let seed = rule.latency.seed();
+        latencies.push(ActiveLatency {
+            rule,
+            rng: SplitMix64::new(seed),
+        });
+        Ok(())
+    }
+
+    pub fn remove_rule(&self, operation: Operation, target: Option<String>) -> Result<bool, BlurmockError> {
+        let mut latencies = match self.latencies.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        let count = latencies.len();
+        latencies.retain(|l| l.rule.operation != operation || l.rule.target != target);
+        Ok(latencies.len() != count)
+    }
+
+    pub fn get_rules(&self) -> Result<Vec<LatencyRule>, BlurmockError> {
+        match self.latencies.lock() {
+            Ok(latencies) => Ok(latencies.iter().map(|l| l.rule.clone()).collect()),
+            Err(_) => Err(BlurmockError::LockPoisoned),
+        }
+    }
+
+    pub fn clear(&self) -> Result<(), BlurmockError> {
+        match self.latencies.lock() {
+            Ok(mut latencies) => latencies.clear(),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        }
+        Ok(())
+    }
+
+    // Replaces the rules by copies of those of `source`, keeping their random
+    // state.
+    pub(crate) fn copy_from(&self, source: &LatencyInjector) -> Result<(), BlurmockError> {
+        let latencies = match source.latencies.lock() {
+            Ok(latencies) => latencies.clone(),
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        match self.latencies.lock() {
+            Ok(mut target_latencies) => *target_latencies = latencies,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        }
+        Ok(())
+    }
+
+    /// Draws the delay of a call of `operation` on the object `target`. A rule
+    /// for that object wins over a rule for every object.
+    pub fn delay(&self, operation: Operation, target: &str) -> Result<Duration, BlurmockError> {
+        let mut latencies = match self.latencies.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BlurmockError::LockPoisoned),
+        };
+        let index = latencies.iter()
+                             .position(|l| l.rule.operation == operation &&
+                                           l.rule.target.as_ref().is_some_and(|t| t == target))
+                             .or_else(|| latencies.iter()
+                                                  .position(|l| l.rule.operation == operation && l.rule.target.is_none()));
+        Ok(match index {
+            Some(index) => latencies[index].draw(),
+            None => Duration::from_secs(0),
+        })
+    }
+}
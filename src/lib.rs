@@ -1,13 +1,21 @@
-extern crate hex;
 extern crate core;
+#[cfg(feature = "scenario")]
+#[macro_use]
+extern crate serde;
+#[cfg(feature = "scenario")]
+extern crate serde_json;
+#[cfg(feature = "scenario")]
+extern crate serde_path_to_error;
+#[cfg(feature = "scenario")]
+extern crate toml;
 
 macro_rules! make_getter(
     ($function_name: ident, $attr: ident, $ret_type:ty) => {
-        pub fn $function_name(&self) -> Result<$ret_type, Box<Error>> {
+        pub fn $function_name(&self) -> Result<$ret_type, BlurmockError> {
             let cloned = self.$attr.clone();
             let attr_value = match cloned.lock() {
                 Ok(guard) => guard.deref().clone(),
-                Err(_) => return Err(Box::from("Could not get the value.")),
+                Err(_) => return Err(BlurmockError::LockPoisoned),
             };
             Ok(attr_value)
         }
@@ -25,11 +33,11 @@ macro_rules! make_getter(
     };
 
     ($attr_name: ident) => {
-        pub fn $attr_name(&self) -> Result<bool, Box<Error>> {
+        pub fn $attr_name(&self) -> Result<bool, BlurmockError> {
             let cloned = self.$attr_name.clone();
             let attr_value = match cloned.lock() {
                 Ok(guard) => guard.deref().clone(),
-                Err(_) => return Err(Box::from("Could not get the value.")),
+                Err(_) => return Err(BlurmockError::LockPoisoned),
             };
             Ok(attr_value)
         }
@@ -38,15 +46,15 @@ macro_rules! make_getter(
 
 macro_rules! make_option_getter(
     ($function_name: ident, $attr: ident, $ret_type:ty) => {
-        pub fn $function_name(&self) -> Result<$ret_type, Box<Error>> {
+        pub fn $function_name(&self) -> Result<$ret_type, BlurmockError> {
             let cloned = self.$attr.clone();
             let attr_value = match cloned.lock() {
                 Ok(guard) => guard.deref().clone(),
-                Err(_) => return Err(Box::from("Could not get the value.")),
+                Err(_) => return Err(BlurmockError::LockPoisoned),
             };
             match attr_value {
-                Some(value) => return Ok(value),
-                None => return Err(Box::from("Could not get the value.")),
+                Some(value) => Ok(value),
+                None => Err(BlurmockError::NoValue),
             }
         }
     };
@@ -54,13 +62,18 @@ macro_rules! make_option_getter(
 
 macro_rules! make_setter(
     ($function_name: ident, $attr: ident, $attr_type:ty ) => {
-        pub fn $function_name(&self, value: $attr_type) -> Result<(), Box<Error>> {
+        pub fn $function_name(&self, value: $attr_type) -> Result<(), BlurmockError> {
             let cloned = self.$attr.clone();
             let mut value_to_change = match cloned.lock() {
                 Ok(guard) => guard,
-                Err(_) => return Err(Box::from("Could not get the value.")),
+                Err(_) => return Err(BlurmockError::LockPoisoned),
             };
-            Ok(*value_to_change = value)
+            let old_value = ::std::mem::replace(&mut *value_to_change, value.clone());
+            drop(value_to_change);
+            if old_value != value {
+                self.emit_property_changed(stringify!($attr), old_value.into(), value.into());
+            }
+            Ok(())
         }
     };
 
@@ -69,16 +82,46 @@ macro_rules! make_setter(
             let cloned = self.$attr.clone();
             let mut value_to_change = match cloned.lock() {
                 Ok(guard) => guard,
-                Err(_) => return (),
+                Err(_) => return,
             };
-            *value_to_change = value
+            let old_value = ::std::mem::replace(&mut *value_to_change, value.clone());
+            drop(value_to_change);
+            if old_value != value {
+                self.emit_property_changed(stringify!($attr), old_value.into(), value.into());
+            }
         }
     };
 );
 
+// The first `<prefix><index>` id that none of `ids` has, counting from the
+// number of ids, for builders that generate the ids of new children.
+pub(crate) fn free_child_id(prefix: &str, ids: &[String]) -> String {
+    let mut index = ids.len();
+    loop {
+        let id = format!("{}{:04x}", prefix, index);
+        if !ids.contains(&id) {
+            return id;
+        }
+        index += 1;
+    }
+}
+
+pub mod advertising_data;
+pub mod clock;
+pub mod error;
+pub mod event;
 pub mod fake_adapter;
 pub mod fake_device;
 pub mod fake_service;
 pub mod fake_characteristic;
 pub mod fake_descriptor;
 pub mod fake_discovery_session;
+pub mod fake_manager;
+pub mod fault;
+pub mod flags;
+pub mod latency;
+pub mod modalias;
+pub mod operation;
+#[cfg(feature = "scenario")]
+pub mod scenario;
+pub mod snapshot;
@@ -0,0 +1,172 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ModaliasSource {
+    Usb,
+    Bluetooth,
+}
+
+impl ModaliasSource {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ModaliasSource::Usb => "usb",
+            ModaliasSource::Bluetooth => "bluetooth",
+        }
+    }
+}
+
+impl fmt::Display for ModaliasSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ModaliasSource {
+    type Err = ModaliasParseError;
+
+    fn from_str(source: &str) -> Result<ModaliasSource, ModaliasParseError> {
+        match source {
+            "usb" => Ok(ModaliasSource::Usb),
+            "bluetooth" => Ok(ModaliasSource::Bluetooth),
+            _ => Err(ModaliasParseError::UnknownSource(String::from(source))),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ModaliasParseError {
+    Empty,
+    MissingSeparator,
+    UnknownSource(String),
+    /// The field introduced by the given marker (`v`, `p` or `d`) is absent.
+    MissingField(char),
+    /// The field introduced by the given marker is not an uppercase hex number
+    /// that fits in 32 bits.
+    InvalidField(char, String),
+}
+
+impl fmt::Display for ModaliasParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ModaliasParseError::Empty => f.write_str("The modalias is empty."),
+            ModaliasParseError::MissingSeparator => f.write_str("The modalias has no ':' after the source."),
+            ModaliasParseError::UnknownSource(ref source) => write!(f, "Unknown modalias source: {}.", source),
+            ModaliasParseError::MissingField(marker) => write!(f, "The modalias has no '{}' field.", marker),
+            ModaliasParseError::InvalidField(marker, ref value) =>
+                write!(f, "The modalias '{}' field is not a valid uppercase hex number: {}.", marker, value),
+        }
+    }
+}
+
+impl Error for ModaliasParseError {}
+
+/// A device id in the `<source>:v<vendor>p<product>d<version>` form BlueZ
+/// reports, e.g. `usb:v1D6Bp0246d0525`. The ids are uppercase hexadecimal and
+/// may have any number of digits; the markers must be lowercase. Lowercase hex
+/// digits are rejected, as `d` would be ambiguous with the version marker.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Modalias {
+    pub source: ModaliasSource,
+    pub vendor: u32,
+    pub product: u32,
+    pub version: u32,
+}
+
+impl Modalias {
+    pub fn new(source: ModaliasSource, vendor: u32, product: u32, version: u32) -> Modalias {
+        Modalias {
+            source,
+            vendor,
+            product,
+            version,
+        }
+    }
+}
+
+fn parse_field(marker: char, value: &str) -> Result<u32, ModaliasParseError> {
+    if value.is_empty() || !value.chars().all(|c| c.is_ascii_digit() || ('A'..='F').contains(&c)) {
+        return Err(ModaliasParseError::InvalidField(marker, String::from(value)));
+    }
+    u32::from_str_radix(value, 16).map_err(|_| ModaliasParseError::InvalidField(marker, String::from(value)))
+}
+
+impl FromStr for Modalias {
+    type Err = ModaliasParseError;
+
+    fn from_str(modalias: &str) -> Result<Modalias, ModaliasParseError> {
+        if modalias.is_empty() {
+            return Err(ModaliasParseError::Empty);
+        }
+        let mut parts = modalias.splitn(2, ':');
+        let source = parts.next().unwrap_or("").parse::<ModaliasSource>()?;
+        let ids = match parts.next() {
+            Some(ids) => ids,
+            None => return Err(ModaliasParseError::MissingSeparator),
+        };
+
+        if !ids.starts_with('v') {
+            return Err(ModaliasParseError::MissingField('v'));
+        }
+        let ids = &ids[1..];
+        let product_start = match ids.find('p') {
+            Some(index) => index,
+            None => return Err(ModaliasParseError::MissingField('p')),
+        };
+        let vendor = parse_field('v', &ids[..product_start])?;
+
+        let ids = &ids[product_start + 1..];
+        let version_start = match ids.find('d') {
+            Some(index) => index,
+            None => return Err(ModaliasParseError::MissingField('d')),
+        };
+        let product = parse_field('p', &ids[..version_start])?;
+        let version = parse_field('d', &ids[version_start + 1..])?;
+
+        Ok(Modalias::new(source, vendor, product, version))
+    }
+}
+
+impl fmt::Display for Modalias {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:v{:04X}p{:04X}d{:04X}", self.source, self.vendor, self.product, self.version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Modalias, ModaliasParseError, ModaliasSource};
+
+    #[test]
+    fn parse_and_format_round_trip() {
+        for &(text, modalias) in &[
+            ("usb:v1D6Bp0246d0525", Modalias::new(ModaliasSource::Usb, 0x1D6B, 0x0246, 0x0525)),
+            ("bluetooth:v000Fp1200d1436", Modalias::new(ModaliasSource::Bluetooth, 0x000F, 0x1200, 0x1436)),
+            ("usb:vDEADBEEFp0D01dD0D0", Modalias::new(ModaliasSource::Usb, 0xDEADBEEF, 0x0D01, 0xD0D0)),
+        ] {
+            assert_eq!(text.parse::<Modalias>(), Ok(modalias));
+            assert_eq!(modalias.to_string(), text);
+        }
+        assert_eq!("usb:v1p2d3".parse::<Modalias>().map(|m| m.to_string()), Ok(String::from("usb:v0001p0002d0003")));
+    }
+
+    #[test]
+    fn parse_errors() {
+        for &(text, ref err) in &[
+            ("", ModaliasParseError::Empty),
+            ("usb", ModaliasParseError::MissingSeparator),
+            ("pci:v1D6Bp0246d0525", ModaliasParseError::UnknownSource(String::from("pci"))),
+            ("usb:1D6Bp0246d0525", ModaliasParseError::MissingField('v')),
+            ("usb:v1D6B0246d0525", ModaliasParseError::MissingField('p')),
+            ("usb:v1D6Bp02460525", ModaliasParseError::MissingField('d')),
+            ("usb:vp0246d0525", ModaliasParseError::InvalidField('v', String::new())),
+            ("usb:v1D6Bp02G6d0525", ModaliasParseError::InvalidField('p', String::from("02G6"))),
+            ("usb:v1D6Bp0246d100000000", ModaliasParseError::InvalidField('d', String::from("100000000"))),
+            ("usb:v1d6bp0d01d0100", ModaliasParseError::InvalidField('v', String::from("1d6b"))),
+            ("usb:v1D6Bp0D01d01ab", ModaliasParseError::InvalidField('d', String::from("01ab"))),
+        ] {
+            assert_eq!(text.parse::<Modalias>().as_ref(), Err(err), "{}", text);
+        }
+    }
+}
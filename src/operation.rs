@@ -0,0 +1,53 @@
+use std::fmt;
+
+/// The fake operations that fault and latency rules can target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Operation {
+    StartDiscovery,
+    StopDiscovery,
+    Connect,
+    Disconnect,
+    ConnectProfile,
+    DisconnectProfile,
+    Pair,
+    CancelPairing,
+    GetGattServices,
+    GetGattCharacteristics,
+    GetIncludes,
+    GetGattDescriptors,
+    /// Reading a characteristic or a descriptor.
+    ReadValue,
+    /// Writing a characteristic or a descriptor.
+    WriteValue,
+    StartNotify,
+    StopNotify,
+}
+
+impl Operation {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Operation::StartDiscovery => "start_discovery",
+            Operation::StopDiscovery => "stop_discovery",
+            Operation::Connect => "connect",
+            Operation::Disconnect => "disconnect",
+            Operation::ConnectProfile => "connect_profile",
+            Operation::DisconnectProfile => "disconnect_profile",
+            Operation::Pair => "pair",
+            Operation::CancelPairing => "cancel_pairing",
+            Operation::GetGattServices => "get_gatt_services",
+            Operation::GetGattCharacteristics => "get_gatt_characteristics",
+            Operation::GetIncludes => "get_includes",
+            Operation::GetGattDescriptors => "get_gatt_descriptors",
+            Operation::ReadValue => "read_value",
+            Operation::WriteValue => "write_value",
+            Operation::StartNotify => "start_notify",
+            Operation::StopNotify => "stop_notify",
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
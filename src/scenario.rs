@@ -0,0 +1,835 @@
+use advertising_data::check_uuid;
+use error::BlurmockError;
+use fake_adapter::FakeBluetoothAdapter;
+use fake_characteristic::{FakeBluetoothGATTCharacteristic, FakeBluetoothGATTCharacteristicBuilder};
+use fake_descriptor::{FakeBluetoothGATTDescriptor, FakeBluetoothGATTDescriptorBuilder};
+use fake_device::{FakeBluetoothDevice, FakeBluetoothDeviceBuilder};
+use fake_manager::FakeBluetoothManager;
+use fake_service::{FakeBluetoothGATTService, FakeBluetoothGATTServiceBuilder};
+use flags::{CharacteristicFlags, DescriptorFlags};
+use modalias::Modalias;
+use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+use serde::{Serialize, Serializer};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(PathBuf, io::Error),
+    /// The file is neither `.json` nor `.toml`.
+    UnknownFormat(PathBuf),
+    /// The file is not valid JSON or TOML, or does not fit the scenario format.
+    /// `entry` is the path of the offending entry, like
+    /// `adapters[0].devices[1].services[0].uuid`.
+    Parse { entry: String, message: String },
+    /// The entry was read but its value is not allowed.
+    Invalid { entry: String, message: String },
+    /// `FakeBluetoothAdapter::from_scenario` was used on a scenario that does
+    /// not describe exactly one adapter.
+    AdapterCount(usize),
+    Build(BlurmockError),
+    /// The live state could not be read, or not be written as JSON or TOML.
+    Save(String),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ScenarioError::Io(ref path, ref err) => write!(f, "Could not access {}: {}", path.display(), err),
+            ScenarioError::UnknownFormat(ref path) =>
+                write!(f, "Unknown scenario format: {}, expected a .json or .toml file.", path.display()),
+            ScenarioError::Parse { ref entry, ref message } => write!(f, "Invalid scenario at {}: {}", entry, message),
+            ScenarioError::Invalid { ref entry, ref message } => write!(f, "Invalid scenario at {}: {}.", entry, message),
+            ScenarioError::AdapterCount(count) =>
+                write!(f, "The scenario describes {} adapters, expected exactly one.", count),
+            ScenarioError::Build(ref err) => write!(f, "Could not build the scenario: {}", err),
+            ScenarioError::Save(ref message) => write!(f, "Could not save the scenario: {}", message),
+        }
+    }
+}
+
+impl Error for ScenarioError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ScenarioError::Io(_, ref err) => Some(err),
+            ScenarioError::Build(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl<E: Error> From<serde_path_to_error::Error<E>> for ScenarioError {
+    fn from(err: serde_path_to_error::Error<E>) -> ScenarioError {
+        ScenarioError::Parse {
+            entry: err.path().to_string(),
+            message: err.inner().to_string(),
+        }
+    }
+}
+
+fn invalid<S: Into<String>>(entry: &str, message: S) -> ScenarioError {
+    ScenarioError::Invalid {
+        entry: String::from(entry),
+        message: message.into(),
+    }
+}
+
+/// A value of a characteristic or descriptor, written either as a list of
+/// bytes or as a hex string such as `"0x0a1b"`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScenarioValue(pub Vec<u8>);
+
+impl<'de> Deserialize<'de> for ScenarioValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ScenarioValue, D::Error> {
+        struct ValueVisitor;
+
+        impl<'de> Visitor<'de> for ValueVisitor {
+            type Value = ScenarioValue;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a list of bytes or a hex string")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<ScenarioValue, E> {
+                let hex = value.strip_prefix("0x").unwrap_or(value);
+                if hex.len() % 2 != 0 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(E::invalid_value(de::Unexpected::Str(value), &self));
+                }
+                let bytes = (0..hex.len()).step_by(2)
+                                          .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+                                          .collect::<Result<Vec<u8>, _>>()
+                                          .map_err(|_| E::invalid_value(de::Unexpected::Str(value), &self))?;
+                Ok(ScenarioValue(bytes))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<ScenarioValue, A::Error> {
+                let mut bytes = vec![];
+                while let Some(byte) = seq.next_element::<u8>()? {
+                    bytes.push(byte);
+                }
+                Ok(ScenarioValue(bytes))
+            }
+        }
+
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+impl Serialize for ScenarioValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(&self.0)
+    }
+}
+
+/// The root of a scenario file: the adapters of the fake system with
+/// everything below them. Properties left out keep the builders' defaults.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scenario {
+    pub adapters: Vec<AdapterScenario>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdapterScenario {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub present: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub powered: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discoverable: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pairable: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discoverable_timeout: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pairable_timeout: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modalias: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub uuids: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<DeviceScenario>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceScenario {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub appearance: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paired: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connectable: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connected: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trusted: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blocked: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub legacy_pairing: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rssi: Option<i16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx_power: Option<i16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modalias: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub uuids: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub services: Vec<ServiceScenario>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceScenario {
+    pub uuid: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub primary: Option<bool>,
+    /// The ids of the included services, which must be services of the same
+    /// device.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub includes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub characteristics: Vec<CharacteristicScenario>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CharacteristicScenario {
+    pub uuid: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<ScenarioValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notifying: Option<bool>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub flags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub descriptors: Vec<DescriptorScenario>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DescriptorScenario {
+    pub uuid: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<ScenarioValue>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub flags: Vec<String>,
+}
+
+fn is_json(path: &Path) -> Result<bool, ScenarioError> {
+    match path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()) {
+        Some(ref extension) if extension == "json" => Ok(true),
+        Some(ref extension) if extension == "toml" => Ok(false),
+        _ => Err(ScenarioError::UnknownFormat(path.to_path_buf())),
+    }
+}
+
+// Turns the `NoValue` of an option getter into `None`.
+fn optional<T>(value: Result<T, BlurmockError>) -> Result<Option<T>, BlurmockError> {
+    match value {
+        Ok(value) => Ok(Some(value)),
+        Err(BlurmockError::NoValue) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn non_empty(value: String) -> Option<String> {
+    if value.is_empty() { None } else { Some(value) }
+}
+
+// An empty uuid is allowed, as objects created with `new_empty` have one.
+fn check_scenario_uuid(entry: &str, uuid: &str) -> Result<(), ScenarioError> {
+    if uuid.is_empty() {
+        return Ok(());
+    }
+    check_uuid(uuid).map_err(|_| invalid(entry, format!("invalid uuid \"{}\"", uuid)))
+}
+
+fn check_uuids(entry: &str, uuids: &[String]) -> Result<(), ScenarioError> {
+    for (i, uuid) in uuids.iter().enumerate() {
+        check_scenario_uuid(&format!("{}.uuids[{}]", entry, i), uuid)?;
+    }
+    Ok(())
+}
+
+fn check_modalias(entry: &str, modalias: &Option<String>) -> Result<(), ScenarioError> {
+    if let Some(ref modalias) = *modalias {
+        if let Err(err) = modalias.parse::<Modalias>() {
+            return Err(invalid(&format!("{}.modalias", entry), err.to_string()));
+        }
+    }
+    Ok(())
+}
+
+// Explicit GATT ids have to be unique, or the builders would merge objects.
+fn check_gatt_id(entry: &str, id: &Option<String>, ids: &mut HashSet<String>) -> Result<(), ScenarioError> {
+    if let Some(ref id) = *id {
+        if !ids.insert(id.clone()) {
+            return Err(invalid(&format!("{}.id", entry), format!("duplicate id \"{}\"", id)));
+        }
+    }
+    Ok(())
+}
+
+impl Scenario {
+    pub fn from_json_str(text: &str) -> Result<Scenario, ScenarioError> {
+        let mut deserializer = serde_json::Deserializer::from_str(text);
+        let scenario: Scenario = serde_path_to_error::deserialize(&mut deserializer)?;
+        deserializer.end().map_err(|err| ScenarioError::Parse { entry: String::from("."), message: err.to_string() })?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    pub fn from_toml_str(text: &str) -> Result<Scenario, ScenarioError> {
+        let scenario: Scenario = serde_path_to_error::deserialize(toml::Deserializer::new(text))?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    /// Reads a `.json` or `.toml` scenario file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Scenario, ScenarioError> {
+        let path = path.as_ref();
+        let is_json = is_json(path)?;
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) => return Err(ScenarioError::Io(path.to_path_buf(), err)),
+        };
+        if is_json {
+            Scenario::from_json_str(&text)
+        } else {
+            Scenario::from_toml_str(&text)
+        }
+    }
+
+    /// Fails with `Invalid` for a scenario that could not be loaded again.
+    pub fn to_json_string(&self) -> Result<String, ScenarioError> {
+        self.validate()?;
+        serde_json::to_string_pretty(self).map_err(|err| ScenarioError::Save(err.to_string()))
+    }
+
+    /// Fails with `Invalid` for a scenario that could not be loaded again.
+    pub fn to_toml_string(&self) -> Result<String, ScenarioError> {
+        self.validate()?;
+        toml::to_string_pretty(self).map_err(|err| ScenarioError::Save(err.to_string()))
+    }
+
+    /// Writes the scenario as JSON or TOML, depending on the extension of
+    /// `path`.
+    pub fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), ScenarioError> {
+        let path = path.as_ref();
+        let text = if is_json(path)? {
+            self.to_json_string()?
+        } else {
+            self.to_toml_string()?
+        };
+        fs::write(path, text).map_err(|err| ScenarioError::Io(path.to_path_buf(), err))
+    }
+
+    /// Checks what the file format cannot express: uuids, flags, modaliases
+    /// and unique ids. Done before anything is built, so a bad scenario never
+    /// leaves a half built tree behind.
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        let mut adapter_ids = HashSet::new();
+        for (a, adapter) in self.adapters.iter().enumerate() {
+            let entry = format!("adapters[{}]", a);
+            let adapter_id = adapter.id.clone().unwrap_or_default();
+            if !adapter_ids.insert(adapter_id.clone()) {
+                return Err(invalid(&format!("{}.id", entry), format!("duplicate adapter id \"{}\"", adapter_id)));
+            }
+            check_modalias(&entry, &adapter.modalias)?;
+            check_uuids(&entry, &adapter.uuids)?;
+            let mut device_ids = HashSet::new();
+            let mut gatt_ids = HashSet::new();
+            for (d, device) in adapter.devices.iter().enumerate() {
+                let entry = format!("{}.devices[{}]", entry, d);
+                if device.id.is_empty() {
+                    return Err(invalid(&entry, "missing device id"));
+                }
+                if !device_ids.insert(device.id.clone()) {
+                    return Err(invalid(&format!("{}.id", entry), format!("duplicate device id \"{}\"", device.id)));
+                }
+                check_modalias(&entry, &device.modalias)?;
+                check_uuids(&entry, &device.uuids)?;
+                let service_ids: HashSet<&String> = device.services.iter().filter_map(|s| s.id.as_ref()).collect();
+                for (s, service) in device.services.iter().enumerate() {
+                    let entry = format!("{}.services[{}]", entry, s);
+                    check_scenario_uuid(&format!("{}.uuid", entry), &service.uuid)?;
+                    check_gatt_id(&entry, &service.id, &mut gatt_ids)?;
+                    for (i, include) in service.includes.iter().enumerate() {
+                        if !service_ids.contains(include) {
+                            return Err(invalid(&format!("{}.includes[{}]", entry, i),
+                                               format!("no service of the device has the id \"{}\"", include)));
+                        }
+                    }
+                    for (c, characteristic) in service.characteristics.iter().enumerate() {
+                        let entry = format!("{}.characteristics[{}]", entry, c);
+                        check_scenario_uuid(&format!("{}.uuid", entry), &characteristic.uuid)?;
+                        check_gatt_id(&entry, &characteristic.id, &mut gatt_ids)?;
+                        for (f, flag) in characteristic.flags.iter().enumerate() {
+                            if CharacteristicFlags::from_strings([flag]).is_err() {
+                                return Err(invalid(&format!("{}.flags[{}]", entry, f),
+                                                   format!("unknown characteristic flag \"{}\"", flag)));
+                            }
+                        }
+                        for (i, descriptor) in characteristic.descriptors.iter().enumerate() {
+                            let entry = format!("{}.descriptors[{}]", entry, i);
+                            check_scenario_uuid(&format!("{}.uuid", entry), &descriptor.uuid)?;
+                            check_gatt_id(&entry, &descriptor.id, &mut gatt_ids)?;
+                            for (f, flag) in descriptor.flags.iter().enumerate() {
+                                if DescriptorFlags::from_strings([flag]).is_err() {
+                                    return Err(invalid(&format!("{}.flags[{}]", entry, f),
+                                                       format!("unknown descriptor flag \"{}\"", flag)));
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds every adapter of the scenario with a real clock.
+    pub fn build(&self) -> Result<Vec<Arc<FakeBluetoothAdapter>>, ScenarioError> {
+        self.validate()?;
+        self.adapters.iter().map(AdapterScenario::build).collect::<Result<_, _>>().map_err(ScenarioError::Build)
+    }
+}
+
+impl AdapterScenario {
+    /// Captures the live state of an adapter and of the devices registered on
+    /// it. Devices still waiting to be discovered are left out.
+    pub fn from_adapter(adapter: &FakeBluetoothAdapter) -> Result<AdapterScenario, BlurmockError> {
+        Ok(AdapterScenario {
+            id: Some(adapter.get_id()),
+            address: non_empty(adapter.get_address()?),
+            name: non_empty(adapter.get_name()?),
+            alias: non_empty(adapter.get_alias()?),
+            class: Some(adapter.get_class()?),
+            present: Some(adapter.is_present()?),
+            powered: Some(adapter.is_powered()?),
+            discoverable: Some(adapter.is_discoverable()?),
+            pairable: Some(adapter.is_pairable()?),
+            discoverable_timeout: Some(adapter.get_discoverable_timeout()?),
+            pairable_timeout: Some(adapter.get_pairable_timeout()?),
+            modalias: non_empty(adapter.get_raw_modalias()?),
+            uuids: adapter.get_uuids()?,
+            devices: adapter.get_devices()?
+                            .iter()
+                            .map(|device| DeviceScenario::from_device(device))
+                            .collect::<Result<_, _>>()?,
+        })
+    }
+
+    fn build(&self) -> Result<Arc<FakeBluetoothAdapter>, BlurmockError> {
+        let mut builder = FakeBluetoothAdapter::builder().uuids(self.uuids.clone());
+        if let Some(ref id) = self.id {
+            builder = builder.id(id.clone());
+        }
+        if let Some(ref address) = self.address {
+            builder = builder.address(address.clone());
+        }
+        if let Some(ref name) = self.name {
+            builder = builder.name(name.clone());
+        }
+        if let Some(ref alias) = self.alias {
+            builder = builder.alias(alias.clone());
+        }
+        if let Some(class) = self.class {
+            builder = builder.class(class);
+        }
+        if let Some(is_present) = self.present {
+            builder = builder.present(is_present);
+        }
+        if let Some(is_powered) = self.powered {
+            builder = builder.powered(is_powered);
+        }
+        if let Some(is_discoverable) = self.discoverable {
+            builder = builder.discoverable(is_discoverable);
+        }
+        if let Some(is_pairable) = self.pairable {
+            builder = builder.pairable(is_pairable);
+        }
+        if let Some(timeout) = self.discoverable_timeout {
+            builder = builder.discoverable_timeout(timeout);
+        }
+        if let Some(timeout) = self.pairable_timeout {
+            builder = builder.pairable_timeout(timeout);
+        }
+        if let Some(ref modalias) = self.modalias {
+            builder = builder.modalias(modalias.clone());
+        }
+        let adapter = builder.build();
+        for device in &self.devices {
+            let built_device = device.apply(adapter.device(device.id.clone())).build();
+            device.include_services(&built_device)?;
+        }
+        Ok(adapter)
+    }
+}
+
+impl DeviceScenario {
+    /// Captures a device with its whole GATT tree, including the services of
+    /// a disconnected device.
+    pub fn from_device(device: &FakeBluetoothDevice) -> Result<DeviceScenario, BlurmockError> {
+        Ok(DeviceScenario {
+            id: device.get_id(),
+            address: non_empty(device.get_address()?),
+            name: optional(device.get_name())?,
+            alias: non_empty(device.get_alias()?),
+            icon: non_empty(device.get_icon()?),
+            class: Some(device.get_class()?),
+            appearance: optional(device.get_appearance())?,
+            paired: Some(device.is_paired()?),
+            connectable: Some(device.is_connectable()?),
+            connected: Some(device.is_connected()?),
+            trusted: Some(device.is_trusted()?),
+            blocked: Some(device.is_blocked()?),
+            legacy_pairing: Some(device.is_legacy_pairing()?),
+            rssi: optional(device.get_rssi())?,
+            tx_power: optional(device.get_tx_power())?,
+            modalias: non_empty(device.get_raw_modalias()?),
+            uuids: device.get_uuids()?,
+            services: device.get_gatt_service_list()?
+                            .iter()
+                            .map(|service| ServiceScenario::from_service(service))
+                            .collect::<Result<_, _>>()?,
+        })
+    }
+
+    fn apply(&self, mut builder: FakeBluetoothDeviceBuilder) -> FakeBluetoothDeviceBuilder {
+        builder = builder.uuids(self.uuids.clone());
+        if let Some(ref address) = self.address {
+            builder = builder.address(address.clone());
+        }
+        if let Some(ref name) = self.name {
+            builder = builder.name(name.clone());
+        }
+        if let Some(ref alias) = self.alias {
+            builder = builder.alias(alias.clone());
+        }
+        if let Some(ref icon) = self.icon {
+            builder = builder.icon(icon.clone());
+        }
+        if let Some(class) = self.class {
+            builder = builder.class(class);
+        }
+        if let Some(appearance) = self.appearance {
+            builder = builder.appearance(appearance);
+        }
+        if let Some(is_paired) = self.paired {
+            builder = builder.paired(is_paired);
+        }
+        if let Some(is_connectable) = self.connectable {
+            builder = builder.connectable(is_connectable);
+        }
+        if let Some(is_connected) = self.connected {
+            builder = builder.connected(is_connected);
+        }
+        if let Some(is_trusted) = self.trusted {
+            builder = builder.trusted(is_trusted);
+        }
+        if let Some(is_blocked) = self.blocked {
+            builder = builder.blocked(is_blocked);
+        }
+        if let Some(is_legacy_pairing) = self.legacy_pairing {
+            builder = builder.legacy_pairing(is_legacy_pairing);
+        }
+        if let Some(rssi) = self.rssi {
+            builder = builder.rssi(rssi);
+        }
+        if let Some(tx_power) = self.tx_power {
+            builder = builder.tx_power(tx_power);
+        }
+        if let Some(ref modalias) = self.modalias {
+            builder = builder.modalias(modalias.clone());
+        }
+        for service in &self.services {
+            builder = builder.service(service.uuid.clone(), |s| service.apply(s));
+        }
+        builder
+    }
+
+    // Links the included services once every service of the device exists.
+    fn include_services(&self, device: &FakeBluetoothDevice) -> Result<(), BlurmockError> {
+        let services = device.get_gatt_service_list()?;
+        for (scenario, service) in self.services.iter().zip(&services) {
+            if scenario.includes.is_empty() {
+                continue;
+            }
+            service.set_includes(scenario.includes
+                                         .iter()
+                                         .filter_map(|id| services.iter().find(|s| s.get_id() == *id).cloned())
+                                         .collect())?;
+        }
+        Ok(())
+    }
+}
+
+impl ServiceScenario {
+    pub fn from_service(service: &FakeBluetoothGATTService) -> Result<ServiceScenario, BlurmockError> {
+        Ok(ServiceScenario {
+            uuid: service.get_uuid()?,
+            id: Some(service.get_id()),
+            primary: Some(service.is_primary()?),
+            includes: service.get_included_services()?.iter().map(|included| included.get_id()).collect(),
+            characteristics: service.get_gatt_characteristic_list()?
+                                    .iter()
+                                    .map(|characteristic| CharacteristicScenario::from_characteristic(characteristic))
+                                    .collect::<Result<_, _>>()?,
+        })
+    }
+
+    fn apply(&self, mut builder: FakeBluetoothGATTServiceBuilder) -> FakeBluetoothGATTServiceBuilder {
+        if let Some(ref id) = self.id {
+            builder = builder.id(id.clone());
+        }
+        if let Some(is_primary) = self.primary {
+            builder = builder.primary(is_primary);
+        }
+        for characteristic in &self.characteristics {
+            builder = builder.characteristic(characteristic.uuid.clone(), |c| characteristic.apply(c));
+        }
+        builder
+    }
+}
+
+impl CharacteristicScenario {
+    pub fn from_characteristic(characteristic: &FakeBluetoothGATTCharacteristic)
+                               -> Result<CharacteristicScenario, BlurmockError> {
+        Ok(CharacteristicScenario {
+            uuid: characteristic.get_uuid()?,
+            id: Some(characteristic.get_id()),
+            value: optional(characteristic.get_value())?.map(ScenarioValue),
+            notifying: Some(characteristic.is_notifying()?),
+            flags: characteristic.get_flags()?,
+            descriptors: characteristic.get_gatt_descriptor_list()?
+                                       .iter()
+                                       .map(|descriptor| DescriptorScenario::from_descriptor(descriptor))
+                                       .collect::<Result<_, _>>()?,
+        })
+    }
+
+    fn apply(&self, mut builder: FakeBluetoothGATTCharacteristicBuilder) -> FakeBluetoothGATTCharacteristicBuilder {
+        builder = builder.flags(self.flags.clone());
+        if let Some(ref id) = self.id {
+            builder = builder.id(id.clone());
+        }
+        if let Some(ref value) = self.value {
+            builder = builder.value(value.0.clone());
+        }
+        if let Some(is_notifying) = self.notifying {
+            builder = builder.notifying(is_notifying);
+        }
+        for descriptor in &self.descriptors {
+            builder = builder.descriptor(descriptor.uuid.clone(), |d| descriptor.apply(d));
+        }
+        builder
+    }
+}
+
+impl DescriptorScenario {
+    pub fn from_descriptor(descriptor: &FakeBluetoothGATTDescriptor) -> Result<DescriptorScenario, BlurmockError> {
+        Ok(DescriptorScenario {
+            uuid: descriptor.get_uuid()?,
+            id: Some(descriptor.get_id()),
+            value: optional(descriptor.get_value())?.map(ScenarioValue),
+            flags: descriptor.get_flags()?,
+        })
+    }
+
+    fn apply(&self, mut builder: FakeBluetoothGATTDescriptorBuilder) -> FakeBluetoothGATTDescriptorBuilder {
+        builder = builder.flags(self.flags.clone());
+        if let Some(ref id) = self.id {
+            builder = builder.id(id.clone());
+        }
+        if let Some(ref value) = self.value {
+            builder = builder.value(value.0.clone());
+        }
+        builder
+    }
+}
+
+impl FakeBluetoothAdapter {
+    /// Loads an adapter with its devices and GATT trees from a `.json` or
+    /// `.toml` scenario file describing exactly one adapter.
+    pub fn from_scenario<P: AsRef<Path>>(path: P) -> Result<Arc<FakeBluetoothAdapter>, ScenarioError> {
+        let scenario = Scenario::from_file(path)?;
+        if scenario.adapters.len() != 1 {
+            return Err(ScenarioError::AdapterCount(scenario.adapters.len()));
+        }
+        scenario.adapters[0].build().map_err(ScenarioError::Build)
+    }
+
+    /// Captures the live state of the adapter tree. Fails with `Invalid`,
+    /// naming the entry, if the state could not be loaded again, for example
+    /// because of a malformed uuid or an unknown flag.
+    pub fn to_scenario(&self) -> Result<Scenario, ScenarioError> {
+        let adapter = AdapterScenario::from_adapter(self).map_err(|err| ScenarioError::Save(err.to_string()))?;
+        let scenario = Scenario { adapters: vec![adapter] };
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    /// Saves the live state of the adapter tree as a `.json` or `.toml`
+    /// scenario file that `from_scenario` can load again.
+    pub fn save_scenario<P: AsRef<Path>>(&self, path: P) -> Result<(), ScenarioError> {
+        self.to_scenario()?.to_file(path)
+    }
+}
+
+impl FakeBluetoothManager {
+    /// Loads every adapter of a `.json` or `.toml` scenario file.
+    pub fn from_scenario<P: AsRef<Path>>(path: P) -> Result<Arc<FakeBluetoothManager>, ScenarioError> {
+        let manager = FakeBluetoothManager::new();
+        for adapter in Scenario::from_file(path)?.build()? {
+            manager.add_adapter(adapter).map_err(ScenarioError::Build)?;
+        }
+        Ok(manager)
+    }
+
+    pub fn to_scenario(&self) -> Result<Scenario, ScenarioError> {
+        let adapters = self.get_adapters()
+                           .and_then(|adapters| adapters.iter()
+                                                        .map(|adapter| AdapterScenario::from_adapter(adapter))
+                                                        .collect::<Result<_, _>>())
+                           .map_err(|err| ScenarioError::Save(err.to_string()))?;
+        let scenario = Scenario { adapters };
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    pub fn save_scenario<P: AsRef<Path>>(&self, path: P) -> Result<(), ScenarioError> {
+        self.to_scenario()?.to_file(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fake_adapter::FakeBluetoothAdapter;
+    use fake_characteristic::FakeBluetoothGATTCharacteristic;
+    use fake_device::FakeBluetoothDevice;
+    use fake_service::FakeBluetoothGATTService;
+    use std::fs;
+    use std::sync::Arc;
+    use super::ScenarioError;
+
+    #[test]
+    fn saved_empty_objects_load_again() {
+        let adapter = FakeBluetoothAdapter::builder().id("hci0").build();
+        let device = FakeBluetoothDevice::new_empty(adapter.clone(), String::from("device"));
+        let service = FakeBluetoothGATTService::new_empty(device.clone(), String::from("service"));
+        FakeBluetoothGATTCharacteristic::new_empty(service, String::from("characteristic"));
+
+        for extension in ["json", "toml"] {
+            let path = ::std::env::temp_dir().join(format!("blurmock-empty-{}.{}", ::std::process::id(), extension));
+            adapter.save_scenario(&path).unwrap();
+            let loaded = FakeBluetoothAdapter::from_scenario(&path);
+            fs::remove_file(&path).unwrap();
+            assert_eq!(loaded.unwrap().to_scenario().unwrap(), adapter.to_scenario().unwrap());
+        }
+    }
+
+    #[test]
+    fn unloadable_state_is_not_saved() {
+        let adapter = FakeBluetoothAdapter::builder().id("hci0").build();
+        let device = FakeBluetoothDevice::new_empty(adapter.clone(), String::from("device"));
+        let service = FakeBluetoothGATTService::new_empty(device, String::from("service"));
+        service.set_uuid(String::from("not a uuid")).unwrap();
+
+        match adapter.to_scenario() {
+            Err(ScenarioError::Invalid { entry, .. }) => assert!(entry.contains("services"), "{}", entry),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn included_services_are_saved() {
+        let adapter = FakeBluetoothAdapter::builder().id("hci0").build();
+        let device = adapter.device("device")
+                            .service("0000180f-0000-1000-8000-00805f9b34fb", |s| s.id("battery"))
+                            .service("00001800-0000-1000-8000-00805f9b34fb", |s| s.id("access"))
+                            .build();
+        let services = device.get_gatt_service_list().unwrap();
+        services[1].add_included_service(services[0].clone()).unwrap();
+
+        for extension in ["json", "toml"] {
+            let path = ::std::env::temp_dir().join(format!("blurmock-includes-{}.{}", ::std::process::id(), extension));
+            adapter.save_scenario(&path).unwrap();
+            let loaded = FakeBluetoothAdapter::from_scenario(&path);
+            fs::remove_file(&path).unwrap();
+            let loaded = loaded.unwrap();
+            let scenario = loaded.to_scenario().unwrap();
+            assert_eq!(scenario.adapters[0].devices[0].services[1].includes, [String::from("battery")]);
+            assert_eq!(scenario, adapter.to_scenario().unwrap());
+
+            let services = loaded.get_device(String::from("device")).unwrap().get_gatt_service_list().unwrap();
+            let included = services[1].get_included_services().unwrap();
+            assert!(Arc::ptr_eq(&included[0], &services[0]));
+        }
+    }
+
+    #[test]
+    fn unknown_included_services_are_rejected() {
+        let mut scenario = FakeBluetoothAdapter::builder()
+            .id("hci0")
+            .device("device", |d| d.service("0000180f-0000-1000-8000-00805f9b34fb", |s| s.id("battery")))
+            .build()
+            .to_scenario()
+            .unwrap();
+        scenario.adapters[0].devices[0].services[0].includes.push(String::from("missing"));
+        match scenario.validate() {
+            Err(ScenarioError::Invalid { entry, .. }) =>
+                assert_eq!(entry, "adapters[0].devices[0].services[0].includes[0]"),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn modaliases_are_saved_as_stored() {
+        let adapter = FakeBluetoothAdapter::builder().id("hci0").modalias("usb:v1D6Bp246d525").build();
+        let scenario = adapter.to_scenario().unwrap();
+        assert_eq!(scenario.adapters[0].modalias, Some(String::from("usb:v1D6Bp246d525")));
+
+        adapter.set_modalias(String::from("usb:vXYZ")).unwrap();
+        match adapter.to_scenario() {
+            Err(ScenarioError::Invalid { entry, .. }) => assert_eq!(entry, "adapters[0].modalias"),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use core::ops::Deref;
+use error::BlurmockError;
+use fake_adapter::FakeBluetoothAdapter;
+use std::sync::{Arc, Mutex};
+
+/// A deep copy of an adapter tree taken by `FakeBluetoothAdapter::snapshot`.
+/// The copy cannot be changed: `restore` and `fork` copy it again, so one
+/// snapshot can be used any number of times.
+#[derive(Clone, Debug)]
+pub struct AdapterSnapshot {
+    adapter: Arc<FakeBluetoothAdapter>,
+}
+
+impl AdapterSnapshot {
+    pub(crate) fn new(adapter: Arc<FakeBluetoothAdapter>) -> AdapterSnapshot {
+        AdapterSnapshot { adapter }
+    }
+
+    pub(crate) fn get_adapter(&self) -> &FakeBluetoothAdapter {
+        &self.adapter
+    }
+
+    pub fn get_id(&self) -> String {
+        self.adapter.get_id()
+    }
+
+    pub fn get_device_list(&self) -> Result<Vec<String>, BlurmockError> {
+        self.adapter.get_device_list()
+    }
+
+    /// Creates a new, independent adapter tree in the state of the snapshot.
+    pub fn fork(&self) -> Result<Arc<FakeBluetoothAdapter>, BlurmockError> {
+        self.adapter.fork()
+    }
+}
+
+// A new mutex holding a copy of the value, for the deep copies of the fake
+// objects.
+pub(crate) fn copy_value<T: Clone>(value: &Arc<Mutex<T>>) -> Result<Arc<Mutex<T>>, BlurmockError> {
+    match value.lock() {
+        Ok(guard) => Ok(Arc::new(Mutex::new(guard.deref().clone()))),
+        Err(_) => Err(BlurmockError::LockPoisoned),
+    }
+}
+
+pub(crate) fn restore_value<T: Clone>(target: &Arc<Mutex<T>>, source: &Arc<Mutex<T>>) -> Result<(), BlurmockError> {
+    let value = match source.lock() {
+        Ok(guard) => guard.deref().clone(),
+        Err(_) => return Err(BlurmockError::LockPoisoned),
+    };
+    match target.lock() {
+        Ok(mut guard) => *guard = value,
+        Err(_) => return Err(BlurmockError::LockPoisoned),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use error::BlurmockError;
+    use fake_adapter::FakeBluetoothAdapter;
+    use fault::FaultRule;
+    use operation::Operation;
+
+    #[test]
+    fn copies_are_not_discovering() {
+        let adapter = FakeBluetoothAdapter::builder().powered(true).build();
+        let session = adapter.create_discovery_session().unwrap();
+        session.start_discovery().unwrap();
+        let snapshot = adapter.snapshot().unwrap();
+
+        let fork = snapshot.fork().unwrap();
+        assert_eq!(fork.is_discovering(), Ok(false));
+        assert_eq!(fork.get_discovery_session_count(), Ok(0));
+
+        adapter.restore(&snapshot).unwrap();
+        assert_eq!(adapter.is_discovering(), Ok(false));
+        assert_eq!(adapter.get_discovery_session_count(), Ok(0));
+
+        let session = adapter.create_discovery_session().unwrap();
+        session.start_discovery().unwrap();
+        assert_eq!(adapter.is_discovering(), Ok(true));
+        session.stop_discovery().unwrap();
+        assert_eq!(adapter.is_discovering(), Ok(false));
+    }
+
+    #[test]
+    fn copies_are_not_notifying() {
+        let adapter = FakeBluetoothAdapter::builder().powered(true).build();
+        let device = adapter.device("device")
+                            .connectable(true)
+                            .service("0000180f-0000-1000-8000-00805f9b34fb", |s| {
+                                s.characteristic("00002a19-0000-1000-8000-00805f9b34fb", |c| c.id("level").flags(["notify"]))
+                            })
+                            .build();
+        device.connect().unwrap();
+        let characteristic = device.get_gatt_service_list().unwrap()[0].get_gatt_characteristic_list().unwrap()[0].clone();
+        let _receiver = characteristic.start_notify().unwrap();
+        let snapshot = adapter.snapshot().unwrap();
+
+        let fork = snapshot.fork().unwrap();
+        adapter.restore(&snapshot).unwrap();
+        for adapter in [adapter, fork] {
+            let device = adapter.get_device(String::from("device")).unwrap();
+            let characteristic = device.get_gatt_service_list().unwrap()[0]
+                                       .get_gatt_characteristic_list().unwrap()[0].clone();
+            assert_eq!(characteristic.is_notifying(), Ok(false));
+            let receiver = characteristic.start_notify().unwrap();
+            assert_eq!(characteristic.is_notifying(), Ok(true));
+            characteristic.notify_value(vec![42]).unwrap();
+            assert_eq!(receiver.try_recv(), Ok(vec![42]));
+            characteristic.stop_notify().unwrap();
+            assert_eq!(characteristic.is_notifying(), Ok(false));
+        }
+    }
+
+    #[test]
+    fn copies_keep_fault_state() {
+        let adapter = FakeBluetoothAdapter::builder().powered(true).build();
+        let device = adapter.device("device").build();
+        adapter.inject_fault(FaultRule::new(Operation::Pair, BlurmockError::NotPermitted).next(2)).unwrap();
+        adapter.inject_fault(FaultRule::new(Operation::CancelPairing, BlurmockError::InProgress)
+                                 .with_probability(0.5, 7)).unwrap();
+        assert_eq!(device.pair(), Err(BlurmockError::NotPermitted));
+        let _ = device.cancel_pairing();
+        let snapshot = adapter.snapshot().unwrap();
+        let expected: Vec<_> = (0..16).map(|_| device.cancel_pairing().is_ok()).collect();
+
+        adapter.restore(&snapshot).unwrap();
+        let fork = snapshot.fork().unwrap();
+        for adapter in [adapter, fork] {
+            let device = adapter.get_device(String::from("device")).unwrap();
+            assert_eq!(device.pair(), Err(BlurmockError::NotPermitted));
+            assert_eq!(device.pair(), Ok(()));
+            let outcomes: Vec<_> = (0..16).map(|_| device.cancel_pairing().is_ok()).collect();
+            assert_eq!(outcomes, expected);
+        }
+    }
+}